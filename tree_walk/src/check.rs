@@ -0,0 +1,94 @@
+use crate::parse::{self, parse_program_recovering};
+use crate::resolve::{self, resolve};
+use crate::scan::{self, Source};
+use std::collections::VecDeque;
+
+/// Anything found wrong with a script without running it.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Scan(scan::Error),
+    Parse { error: parse::Error, line: usize },
+    Resolve(resolve::Error),
+}
+
+impl Error {
+    pub fn line(&self) -> usize {
+        match self {
+            Error::Scan(error) => error.line(),
+            Error::Parse { line, .. } => *line,
+            Error::Resolve(error) => error.line(),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Scan(error) => write!(f, "{}", error),
+            Error::Parse { error, .. } => write!(f, "{}", error),
+            Error::Resolve(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Scans, parses and resolves a script, carrying on past errors to report
+/// all of them in line order.
+pub fn check(source: String) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let mut tokens = VecDeque::new();
+    for token in Source::new(source) {
+        match token {
+            Ok(token) => tokens.push_back(token),
+            Err(error) => errors.push(Error::Scan(error)),
+        }
+    }
+    let (statements, parse_errors) = parse_program_recovering(tokens);
+    errors.extend(
+        parse_errors
+            .into_iter()
+            .map(|(error, line)| Error::Parse { error, line }),
+    );
+    errors.extend(resolve(&statements).into_iter().map(Error::Resolve));
+    errors.sort_by_key(Error::line);
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checked(source: &str) -> Vec<(usize, String)> {
+        check(source.to_string())
+            .iter()
+            .map(|error| (error.line(), error.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean() {
+        assert!(checked("var a = 1;\n{ var b = a; print b; }").is_empty());
+        assert!(checked("").is_empty());
+    }
+
+    #[test]
+    fn test_every_error() {
+        assert_eq!(
+            vec![
+                (0, "Unexpected character '#'.".to_string()),
+                (1, "Expect expression.".to_string()),
+                (
+                    2,
+                    "Can't read local variable 'b' in its own initializer.".to_string()
+                ),
+                (3, "Unterminated string.".to_string()),
+            ],
+            checked("print 1 #;\nprint 1 + ;\n{ var b = b; }\n\"oops;")
+        );
+    }
+
+    #[test]
+    fn test_does_not_run() {
+        // a runtime error is no business of check
+        assert!(checked("print -nil;").is_empty());
+    }
+}
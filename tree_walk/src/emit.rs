@@ -0,0 +1,519 @@
+//! Machine-readable dumps of the scanner and parser output.
+//!
+//! Lines are counted from 1 and spans are byte offsets into the source,
+//! `start` inclusive and `end` exclusive.
+//!
+//! `tokens` prints one token per line as tab-separated `line`, `start`, `end`
+//! and the token's type.
+//!
+//! `sexpr` prints one top-level statement per line in the parenthesized
+//! notation of `expression::print`.
+//!
+//! `json` prints a program as an object of this shape:
+//!
+//! ```text
+//! program    := { "type": "Program", "statements": [statement] }
+//! statement  := { "type": "Expression", "expression": expression }
+//!             | { "type": "Print", "expression": expression }
+//!             | { "type": "Var", "name": token, "initializer": expression | null }
+//!             | { "type": "Block", "statements": [statement] }
+//! expression := { "type": "Literal", "value": value, "line": number }
+//!             | { "type": "Unary", "operator": token, "expression": expression }
+//!             | { "type": "Binary", "left": expression, "operator": token, "right": expression }
+//!             | { "type": "Grouping", "expression": expression }
+//!             | { "type": "Variable", "name": token }
+//!             | { "type": "Assign", "name": token, "value": expression }
+//!             | { "type": "Call", "callee": expression, "paren": token, "arguments": [expression] }
+//! token      := { "lexeme": string, "line": number, "span": { "start": number, "end": number } }
+//! value      := number | string | true | false | null
+//! ```
+//!
+//! Numbers that JSON can't represent are written as the strings `"inf"`,
+//! `"-inf"` and `"NaN"`. A literal keeps only its token's line in the AST, so
+//! it has a line but no span. A grouping keeps no tokens, so it has neither;
+//! its parentheses are only in the source.
+//!
+//! `dot` prints a Graphviz digraph with one node per statement and expression.
+
+use crate::expression::{print, Expression, Value};
+use crate::scan::Token;
+use crate::statement::Statement;
+
+pub fn tokens(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        out.push_str(&format!(
+            "{}\t{}\t{}\t{:?}\n",
+            token.line() + 1,
+            token.span().start,
+            token.span().end,
+            token.token_type()
+        ));
+    }
+    out
+}
+
+pub fn sexpr(statements: &[Statement]) -> String {
+    let mut out = String::new();
+    for statement in statements {
+        out.push_str(&sexpr_statement(statement));
+        out.push('\n');
+    }
+    out
+}
+
+fn sexpr_statement(statement: &Statement) -> String {
+    match statement {
+        Statement::Expression { expression } => print(expression),
+        Statement::Print { expression } => format!("(print {})", print(expression)),
+        Statement::Var {
+            name,
+            initializer: Some(initializer),
+        } => format!("(var {} {})", name, print(initializer)),
+        Statement::Var {
+            name,
+            initializer: None,
+        } => format!("(var {})", name),
+        Statement::Block { statements } => {
+            let mut string = "(block".to_string();
+            for statement in statements {
+                string = format!("{} {}", string, sexpr_statement(statement));
+            }
+            string.push(')');
+            string
+        }
+    }
+}
+
+enum Json {
+    Null,
+    Bool(bool),
+    // already rendered, since the AST holds both f32 and i64 numbers
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn write(&self, out: &mut String, indent: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(&b.to_string()),
+            Json::Number(n) => out.push_str(n),
+            Json::String(s) => quote(s, out),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, indent + 1);
+                    item.write(out, indent + 1);
+                }
+                newline(out, indent);
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, indent + 1);
+                    quote(key, out);
+                    out.push_str(": ");
+                    value.write(out, indent + 1);
+                }
+                newline(out, indent);
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn newline(out: &mut String, indent: usize) {
+    out.push('\n');
+    out.push_str(&"  ".repeat(indent));
+}
+
+fn quote(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+pub fn json(statements: &[Statement]) -> String {
+    let program = Json::Object(vec![
+        ("type", Json::String("Program".to_string())),
+        (
+            "statements",
+            Json::Array(statements.iter().map(json_statement).collect()),
+        ),
+    ]);
+    let mut out = String::new();
+    program.write(&mut out, 0);
+    out.push('\n');
+    out
+}
+
+fn json_type(name: &str) -> (&'static str, Json) {
+    ("type", Json::String(name.to_string()))
+}
+
+fn json_token(token: &Token) -> Json {
+    Json::Object(vec![
+        ("lexeme", Json::String(token.to_string())),
+        ("line", Json::Number((token.line() + 1).to_string())),
+        (
+            "span",
+            Json::Object(vec![
+                ("start", Json::Number(token.span().start.to_string())),
+                ("end", Json::Number(token.span().end.to_string())),
+            ]),
+        ),
+    ])
+}
+
+fn json_statement(statement: &Statement) -> Json {
+    match statement {
+        Statement::Expression { expression } => Json::Object(vec![
+            json_type("Expression"),
+            ("expression", json_expression(expression)),
+        ]),
+        Statement::Print { expression } => Json::Object(vec![
+            json_type("Print"),
+            ("expression", json_expression(expression)),
+        ]),
+        Statement::Var { name, initializer } => Json::Object(vec![
+            json_type("Var"),
+            ("name", json_token(name)),
+            (
+                "initializer",
+                initializer
+                    .as_ref()
+                    .map_or(Json::Null, |initializer| json_expression(initializer)),
+            ),
+        ]),
+        Statement::Block { statements } => Json::Object(vec![
+            json_type("Block"),
+            (
+                "statements",
+                Json::Array(statements.iter().map(json_statement).collect()),
+            ),
+        ]),
+    }
+}
+
+fn json_value(value: &Value) -> Json {
+    match value {
+        Value::Str(s) => Json::String(s.clone()),
+        Value::Int(i) => Json::Number(i.to_string()),
+        Value::Float(f) if f.is_finite() => Json::Number(f.to_string()),
+        Value::Float(f) => Json::String(f.to_string()),
+        Value::False => Json::Bool(false),
+        Value::True => Json::Bool(true),
+        Value::Nil => Json::Null,
+        Value::List(values) => Json::Array(values.iter().map(json_value).collect()),
+        Value::Native(native) => Json::String(native.name().to_string()),
+    }
+}
+
+fn json_expression(expression: &Expression) -> Json {
+    match expression {
+        Expression::Literal { value, line } => Json::Object(vec![
+            json_type("Literal"),
+            ("value", json_value(value)),
+            ("line", Json::Number((line + 1).to_string())),
+        ]),
+        Expression::Unary {
+            operator,
+            expression,
+        } => Json::Object(vec![
+            json_type("Unary"),
+            ("operator", json_token(operator)),
+            ("expression", json_expression(expression)),
+        ]),
+        Expression::Binary {
+            left,
+            operator,
+            right,
+        } => Json::Object(vec![
+            json_type("Binary"),
+            ("left", json_expression(left)),
+            ("operator", json_token(operator)),
+            ("right", json_expression(right)),
+        ]),
+        Expression::Grouping { expression } => Json::Object(vec![
+            json_type("Grouping"),
+            ("expression", json_expression(expression)),
+        ]),
+        Expression::Variable { name } => {
+            Json::Object(vec![json_type("Variable"), ("name", json_token(name))])
+        }
+        Expression::Assign { name, value } => Json::Object(vec![
+            json_type("Assign"),
+            ("name", json_token(name)),
+            ("value", json_expression(value)),
+        ]),
+        Expression::Call {
+            callee,
+            paren,
+            arguments,
+        } => Json::Object(vec![
+            json_type("Call"),
+            ("callee", json_expression(callee)),
+            ("paren", json_token(paren)),
+            (
+                "arguments",
+                Json::Array(arguments.iter().map(json_expression).collect()),
+            ),
+        ]),
+    }
+}
+
+struct Graph {
+    out: String,
+    nodes: usize,
+}
+
+impl Graph {
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.nodes;
+        self.nodes += 1;
+        let mut quoted = String::new();
+        quote(label, &mut quoted);
+        self.out
+            .push_str(&format!("    n{} [label={}];\n", id, quoted));
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize) {
+        self.out.push_str(&format!("    n{} -> n{};\n", from, to));
+    }
+
+    fn statement(&mut self, statement: &Statement) -> usize {
+        match statement {
+            Statement::Expression { expression } => {
+                let id = self.node(";");
+                let child = self.expression(expression);
+                self.edge(id, child);
+                id
+            }
+            Statement::Print { expression } => {
+                let id = self.node("print");
+                let child = self.expression(expression);
+                self.edge(id, child);
+                id
+            }
+            Statement::Var { name, initializer } => {
+                let id = self.node(&format!("var {}", name));
+                if let Some(initializer) = initializer {
+                    let child = self.expression(initializer);
+                    self.edge(id, child);
+                }
+                id
+            }
+            Statement::Block { statements } => {
+                let id = self.node("block");
+                for statement in statements {
+                    let child = self.statement(statement);
+                    self.edge(id, child);
+                }
+                id
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> usize {
+        match expression {
+            Expression::Literal {
+                value: Value::Str(s),
+                ..
+            } => self.node(&format!("\"{}\"", s)),
+            Expression::Literal { value, .. } => self.node(&value.to_string()),
+            Expression::Unary {
+                operator,
+                expression,
+            } => {
+                let id = self.node(&operator.to_string());
+                let child = self.expression(expression);
+                self.edge(id, child);
+                id
+            }
+            Expression::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let id = self.node(&operator.to_string());
+                let left = self.expression(left);
+                self.edge(id, left);
+                let right = self.expression(right);
+                self.edge(id, right);
+                id
+            }
+            Expression::Grouping { expression } => {
+                let id = self.node("group");
+                let child = self.expression(expression);
+                self.edge(id, child);
+                id
+            }
+            Expression::Variable { name } => self.node(&name.to_string()),
+            Expression::Assign { name, value } => {
+                let id = self.node(&format!("{} =", name));
+                let child = self.expression(value);
+                self.edge(id, child);
+                id
+            }
+            Expression::Call {
+                callee, arguments, ..
+            } => {
+                let id = self.node("call");
+                for child in std::iter::once(&**callee).chain(arguments) {
+                    let child = self.expression(child);
+                    self.edge(id, child);
+                }
+                id
+            }
+        }
+    }
+}
+
+pub fn dot(statements: &[Statement]) -> String {
+    let mut graph = Graph {
+        out: "digraph ast {\n".to_string(),
+        nodes: 0,
+    };
+    let root = graph.node("program");
+    for statement in statements {
+        let child = graph.statement(statement);
+        graph.edge(root, child);
+    }
+    graph.out.push_str("}\n");
+    graph.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_program;
+    use crate::scan::scan_tokens;
+    use std::collections::VecDeque;
+
+    fn program(source: &str) -> Vec<Statement> {
+        parse_program(VecDeque::from(scan_tokens(source.to_string()).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tokens = scan_tokens("print\n  \"hi\";".to_string()).unwrap();
+        assert_eq!(
+            "1\t0\t5\tPrint\n2\t8\t12\tStr(\"hi\")\n2\t12\t13\tSemicolon\n2\t13\t13\tEOF\n",
+            super::tokens(&tokens)
+        );
+    }
+
+    #[test]
+    fn test_sexpr() {
+        assert_eq!(
+            "(var a (+ 1 2))\n(var b)\n(block (print a) (= b a))\n",
+            sexpr(&program("var a = 1 + 2; var b; { print a; b = a; }"))
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let expected = r#"{
+  "type": "Program",
+  "statements": [
+    {
+      "type": "Var",
+      "name": {
+        "lexeme": "a",
+        "line": 1,
+        "span": {
+          "start": 4,
+          "end": 5
+        }
+      },
+      "initializer": null
+    },
+    {
+      "type": "Print",
+      "expression": {
+        "type": "Unary",
+        "operator": {
+          "lexeme": "-",
+          "line": 2,
+          "span": {
+            "start": 13,
+            "end": 14
+          }
+        },
+        "expression": {
+          "type": "Literal",
+          "value": 2.5,
+          "line": 2
+        }
+      }
+    },
+    {
+      "type": "Block",
+      "statements": []
+    },
+    {
+      "type": "Expression",
+      "expression": {
+        "type": "Literal",
+        "value": "a\\b",
+        "line": 3
+      }
+    }
+  ]
+}
+"#;
+        assert_eq!(
+            expected,
+            json(&program("var a;\nprint -2.5; {}\n\"a\\b\";"))
+        );
+    }
+
+    #[test]
+    fn test_json_empty_program() {
+        assert_eq!(
+            "{\n  \"type\": \"Program\",\n  \"statements\": []\n}\n",
+            json(&[])
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let expected = r#"digraph ast {
+    n0 [label="program"];
+    n1 [label="print"];
+    n2 [label="*"];
+    n3 [label="group"];
+    n4 [label="a"];
+    n3 -> n4;
+    n2 -> n3;
+    n5 [label="\"b\""];
+    n2 -> n5;
+    n1 -> n2;
+    n0 -> n1;
+}
+"#;
+        assert_eq!(expected, dot(&program("print (a) * \"b\";")));
+    }
+}
@@ -1,12 +1,19 @@
-use crate::scan::Token;
+use crate::native::Native;
+use crate::scan::{Token, TokenType};
+use std::rc::Rc;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     Str(String),
     Int(i64),
     Float(f32),
     False,
     True,
+    Nil,
+    /// Only made by the host, like the script's `args`; there's no syntax for it.
+    List(Vec<Value>),
+    /// A Rust function registered by the host.
+    Native(Rc<Native>),
 }
 
 impl std::fmt::Display for Value {
@@ -17,6 +24,18 @@ impl std::fmt::Display for Value {
             Value::Float(g) => write!(f, "{}", g),
             Value::False => write!(f, "false"),
             Value::True => write!(f, "true"),
+            Value::Nil => write!(f, "nil"),
+            Value::List(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Native(_) => write!(f, "<native fn>"),
         }
     }
 }
@@ -25,6 +44,8 @@ impl std::fmt::Display for Value {
 pub enum Expression {
     Literal {
         value: Value,
+        /// The line of the literal's token, or of the operator it was folded from.
+        line: usize,
     },
     Unary {
         operator: Token,
@@ -38,11 +59,24 @@ pub enum Expression {
     Grouping {
         expression: Box<Expression>,
     },
+    Variable {
+        name: Token,
+    },
+    Assign {
+        name: Token,
+        value: Box<Expression>,
+    },
+    Call {
+        callee: Box<Expression>,
+        /// The closing parenthesis, whose line errors in the call are reported on.
+        paren: Token,
+        arguments: Vec<Expression>,
+    },
 }
 
 pub fn print(expression: &Expression) -> String {
     match expression {
-        Expression::Literal { value } => value.to_string(),
+        Expression::Literal { value, .. } => value.to_string(),
         Expression::Unary {
             operator,
             expression,
@@ -53,6 +87,15 @@ pub fn print(expression: &Expression) -> String {
             right,
         } => parenthesize(&operator, &[left, right]),
         Expression::Grouping { expression } => parenthesize(&"group".to_string(), &[expression]),
+        Expression::Variable { name } => name.to_string(),
+        Expression::Assign { name, value } => format!("(= {} {})", name, print(value)),
+        Expression::Call {
+            callee, arguments, ..
+        } => {
+            let mut expressions = vec![&**callee];
+            expressions.extend(arguments);
+            parenthesize(&"call", &expressions)
+        }
     }
 }
 
@@ -65,37 +108,221 @@ fn parenthesize(name: &impl std::fmt::Display, expressions: &[&Expression]) -> S
     string
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Notation {
+    /// `(* (group (+ 1 2)) 3)`, as produced by `print`
+    Lisp,
+    /// `1 2 + 3 *`, with unary minus written as `neg` and a call with n
+    /// arguments as `call/n`
+    ReversePolish,
+    /// `(1 + 2) * 3`, as Lox source
+    Infix,
+}
+
+pub fn print_as(expression: &Expression, notation: Notation) -> String {
+    match notation {
+        Notation::Lisp => print(expression),
+        Notation::ReversePolish => print_rpn(expression),
+        Notation::Infix => print_infix(expression, 0),
+    }
+}
+
+fn print_rpn(expression: &Expression) -> String {
+    match expression {
+        Expression::Literal { value, .. } => value.to_string(),
+        Expression::Unary {
+            operator,
+            expression,
+        } => match operator.token_type() {
+            TokenType::Minus => format!("{} neg", print_rpn(expression)),
+            _ => format!("{} {}", print_rpn(expression), operator),
+        },
+        Expression::Binary {
+            left,
+            operator,
+            right,
+        } => format!("{} {} {}", print_rpn(left), print_rpn(right), operator),
+        Expression::Grouping { expression } => print_rpn(expression),
+        Expression::Variable { name } => name.to_string(),
+        Expression::Assign { name, value } => format!("{} {} =", name, print_rpn(value)),
+        Expression::Call {
+            callee, arguments, ..
+        } => {
+            let mut string = print_rpn(callee);
+            for argument in arguments {
+                string = format!("{} {}", string, print_rpn(argument));
+            }
+            format!("{} call/{}", string, arguments.len())
+        }
+    }
+}
+
+pub(crate) const ASSIGNMENT: u8 = 1;
+pub(crate) const UNARY: u8 = 6;
+pub(crate) const PRIMARY: u8 = 7;
+
+pub(crate) fn binding_power(operator: &Token) -> u8 {
+    match operator.token_type() {
+        TokenType::EqualEqual | TokenType::BangEqual => 2,
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => 3,
+        TokenType::Minus | TokenType::Plus => 4,
+        _ => 5,
+    }
+}
+
+pub(crate) fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Assign { .. } => ASSIGNMENT,
+        Expression::Binary { operator, .. } => binding_power(operator),
+        Expression::Unary { .. } => UNARY,
+        // printed with a minus sign, so it binds like one
+        Expression::Literal { value, .. } if is_negative(value) => UNARY,
+        Expression::Literal { .. }
+        | Expression::Grouping { .. }
+        | Expression::Variable { .. }
+        | Expression::Call { .. } => PRIMARY,
+    }
+}
+
+/// A number literal the parser can't produce, since it reads `-1` as a minus
+/// applied to `1`. Folding can make them.
+fn is_negative(value: &Value) -> bool {
+    match value {
+        Value::Int(i) => *i < 0,
+        Value::Float(n) => n.is_sign_negative() && !n.is_nan(),
+        _ => false,
+    }
+}
+
+// Only adds parentheses the tree needs but doesn't have, which never happens
+// for trees from the parser since those keep every grouping, so source
+// printed from a parsed tree parses back to an equal tree. Numbers the parser
+// can't produce, negative or not finite, are printed as expressions that
+// evaluate to them instead.
+fn print_infix(expression: &Expression, minimum: u8) -> String {
+    let string = match expression {
+        Expression::Literal {
+            value: Value::Str(s),
+            ..
+        } => format!("\"{}\"", s),
+        Expression::Literal {
+            value: Value::Float(n),
+            ..
+        } if !n.is_finite() => {
+            let numerator = if n.is_nan() {
+                "0"
+            } else if *n > 0.0 {
+                "1"
+            } else {
+                "-1"
+            };
+            format!("({} / 0)", numerator)
+        }
+        Expression::Literal { value, .. } => value.to_string(),
+        Expression::Unary {
+            operator,
+            expression,
+        } => match &**expression {
+            // not `--1`, which reads as two minuses
+            Expression::Literal { value, .. } if is_negative(value) => {
+                format!("{}({})", operator, value)
+            }
+            _ => format!("{}{}", operator, print_infix(expression, UNARY)),
+        },
+        Expression::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            let power = binding_power(operator);
+            format!(
+                "{} {} {}",
+                print_infix(left, power),
+                operator,
+                print_infix(right, power + 1)
+            )
+        }
+        Expression::Grouping { expression } => format!("({})", print_infix(expression, 0)),
+        Expression::Variable { name } => name.to_string(),
+        Expression::Assign { name, value } => {
+            format!("{} = {}", name, print_infix(value, ASSIGNMENT))
+        }
+        Expression::Call {
+            callee, arguments, ..
+        } => {
+            let arguments: Vec<String> = arguments
+                .iter()
+                .map(|argument| print_infix(argument, ASSIGNMENT))
+                .collect();
+            format!("{}({})", print_infix(callee, PRIMARY), arguments.join(", "))
+        }
+    };
+    if precedence(expression) < minimum {
+        format!("({})", string)
+    } else {
+        string
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::scan::TokenType;
+    use crate::parse::parse;
+    use crate::scan::scan_tokens;
+    use std::collections::VecDeque;
+
+    fn parsed(source: &str) -> Box<Expression> {
+        parse(VecDeque::from(scan_tokens(source.to_string()).unwrap()))
+            .unwrap()
+            .remove(0)
+    }
+
+    fn literal(n: f32) -> Box<Expression> {
+        Box::new(Expression::Literal {
+            value: Value::Float(n),
+            line: 0,
+        })
+    }
+
+    fn binary(left: Box<Expression>, type_: TokenType, right: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::Binary {
+            left,
+            operator: Token::new(type_, 0),
+            right,
+        })
+    }
 
     #[test]
     fn test_expression_instances() {
         let _literal = Expression::Literal {
             value: Value::Float(5.0),
+            line: 0,
         };
 
         let _unary = Expression::Unary {
             operator: Token::new(TokenType::Minus, 0),
             expression: Box::new(Expression::Literal {
                 value: Value::Int(5),
+                line: 0,
             }),
         };
 
         let _binary = Expression::Binary {
             left: Box::new(Expression::Literal {
                 value: Value::Int(5),
+                line: 0,
             }),
             operator: Token::new(TokenType::Plus, 0),
             right: Box::new(Expression::Literal {
                 value: Value::Int(6),
+                line: 0,
             }),
         };
 
         let _grouping = Expression::Grouping {
             expression: Box::new(Expression::Literal {
                 value: Value::Int(5),
+                line: 0,
             }),
         };
     }
@@ -105,17 +332,29 @@ mod tests {
         let expected = "5".to_string();
         let literal = Expression::Literal {
             value: Value::Int(5),
+            line: 0,
         };
 
         assert_eq!(expected, print(&literal));
     }
 
+    #[test]
+    fn test_print_list() {
+        let list = Value::List(vec![
+            Value::Str("a".to_string()),
+            Value::List(vec![]),
+            Value::Nil,
+        ]);
+        assert_eq!("[a, [], nil]", list.to_string());
+    }
+
     #[test]
     fn test_print_unary() {
         let unary = Expression::Unary {
             operator: Token::new(TokenType::Minus, 0),
             expression: Box::new(Expression::Literal {
                 value: Value::Int(5),
+                line: 0,
             }),
         };
         assert_eq!("(- 5)".to_string(), print(&unary));
@@ -126,10 +365,12 @@ mod tests {
         let binary = Expression::Binary {
             left: Box::new(Expression::Literal {
                 value: Value::Int(5),
+                line: 0,
             }),
             operator: Token::new(TokenType::Minus, 0),
             right: Box::new(Expression::Literal {
                 value: Value::Int(6),
+                line: 0,
             }),
         };
         assert_eq!("(- 5 6)", print(&binary));
@@ -140,11 +381,38 @@ mod tests {
         let grouping = Expression::Grouping {
             expression: Box::new(Expression::Literal {
                 value: Value::Int(5),
+                line: 0,
             }),
         };
         assert_eq!("(group 5)".to_string(), print(&grouping));
     }
 
+    #[test]
+    fn test_print_variables() {
+        let assign = Expression::Assign {
+            name: Token::new(TokenType::Identifier("a".to_string()), 0),
+            value: Box::new(Expression::Variable {
+                name: Token::new(TokenType::Identifier("b".to_string()), 0),
+            }),
+        };
+        assert_eq!("(= a b)".to_string(), print(&assign));
+    }
+
+    #[test]
+    fn test_print_call() {
+        let call = parsed("f(1 + 2, g())(-a)");
+        assert_eq!("(call (call f (+ 1 2) (call g)) (- a))", print(&call));
+        assert_eq!(
+            "f 1 2 + g call/0 call/2 a neg call/1",
+            print_as(&call, Notation::ReversePolish)
+        );
+        assert_eq!("f(1 + 2, g())(-a)", print_as(&call, Notation::Infix));
+        assert_eq!(
+            "-(f)(a = 1)",
+            print_as(&parsed("-(f)(a = 1)"), Notation::Infix)
+        );
+    }
+
     #[test]
     fn test_example() {
         let expression = Expression::Binary {
@@ -152,15 +420,149 @@ mod tests {
                 operator: Token::new(TokenType::Minus, 0),
                 expression: Box::new(Expression::Literal {
                     value: Value::Int(123),
+                    line: 0,
                 }),
             }),
             operator: Token::new(TokenType::Star, 0),
             right: Box::new(Expression::Grouping {
                 expression: Box::new(Expression::Literal {
                     value: Value::Float(45.67),
+                    line: 0,
                 }),
             }),
         };
         assert_eq!("(* (- 123) (group 45.67))".to_string(), print(&expression));
     }
+
+    #[test]
+    fn test_print_rpn() {
+        let expression = parsed("(1 + 2) * (4 - 3)");
+        assert_eq!(
+            "1 2 + 4 3 - *",
+            print_as(&expression, Notation::ReversePolish)
+        );
+
+        let expression = parsed("a = -b == !c");
+        assert_eq!(
+            "a b neg c ! == =",
+            print_as(&expression, Notation::ReversePolish)
+        );
+    }
+
+    #[test]
+    fn test_print_lisp() {
+        let expression = parsed("-123 * (45.67)");
+        assert_eq!(print(&expression), print_as(&expression, Notation::Lisp));
+    }
+
+    #[test]
+    fn test_print_infix() {
+        let expression = parsed("1+2*(3-\"four\")");
+        assert_eq!(
+            "1 + 2 * (3 - \"four\")",
+            print_as(&expression, Notation::Infix)
+        );
+    }
+
+    #[test]
+    fn test_print_infix_adds_missing_parentheses() {
+        let left_nested = binary(
+            binary(literal(1.0), TokenType::Minus, literal(2.0)),
+            TokenType::Minus,
+            literal(3.0),
+        );
+        assert_eq!("1 - 2 - 3", print_as(&left_nested, Notation::Infix));
+
+        let right_nested = binary(
+            literal(1.0),
+            TokenType::Minus,
+            binary(literal(2.0), TokenType::Minus, literal(3.0)),
+        );
+        assert_eq!("1 - (2 - 3)", print_as(&right_nested, Notation::Infix));
+
+        let looser_inside = binary(
+            binary(literal(1.0), TokenType::Plus, literal(2.0)),
+            TokenType::Star,
+            literal(3.0),
+        );
+        assert_eq!("(1 + 2) * 3", print_as(&looser_inside, Notation::Infix));
+
+        let unary = Expression::Unary {
+            operator: Token::new(TokenType::Minus, 0),
+            expression: binary(literal(1.0), TokenType::Plus, literal(2.0)),
+        };
+        assert_eq!("-(1 + 2)", print_as(&unary, Notation::Infix));
+    }
+
+    #[test]
+    fn test_print_infix_parses_back() {
+        let sources = [
+            "1",
+            "\"a string\"",
+            "true != false",
+            "nil == nil",
+            "-1.5",
+            "--1",
+            "!!true",
+            "-(1)",
+            "1 + 2 * 3 - 4 / 5",
+            "(1 + 2) * (3 - 4) / 5",
+            "1 - (2 - 3)",
+            "((1))",
+            "1 < 2 == 3 >= 4",
+            "a = b = c + 1",
+            "a = (b = c)",
+            "!(a == b) != c",
+        ];
+        for source in sources {
+            let expression = parsed(source);
+            let printed = print_as(&expression, Notation::Infix);
+            assert_eq!(
+                expression,
+                parsed(&printed),
+                "{} printed as {}",
+                source,
+                printed
+            );
+        }
+    }
+
+    #[test]
+    fn test_print_infix_numbers_the_parser_cannot_make() {
+        let negate = |expression| {
+            Box::new(Expression::Unary {
+                operator: Token::new(TokenType::Minus, 0),
+                expression,
+            })
+        };
+        let cases = [
+            (literal(-1.0), "-1", "-1"),
+            (literal(f32::INFINITY), "(1 / 0)", "inf"),
+            (literal(f32::NEG_INFINITY), "(-1 / 0)", "-inf"),
+            (literal(f32::NAN), "(0 / 0)", "NaN"),
+            (negate(literal(-1.0)), "-(-1)", "1"),
+            (negate(literal(f32::INFINITY)), "-(1 / 0)", "-inf"),
+            (
+                binary(literal(2.0), TokenType::Minus, literal(-1.0)),
+                "2 - -1",
+                "3",
+            ),
+            (
+                binary(literal(-2.0), TokenType::Star, literal(f32::NAN)),
+                "-2 * (0 / 0)",
+                "NaN",
+            ),
+        ];
+        for (expression, expected, value) in cases {
+            let printed = print_as(&expression, Notation::Infix);
+            assert_eq!(expected, printed);
+            // the source evaluates to what the tree does, and prints back the same
+            let reparsed = parsed(&printed);
+            let evaluated = crate::interpret::Interpreter::new()
+                .evaluate(&reparsed)
+                .unwrap();
+            assert_eq!(value, evaluated.to_string(), "{}", printed);
+            assert_eq!(printed, print_as(&reparsed, Notation::Infix));
+        }
+    }
 }
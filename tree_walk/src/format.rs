@@ -0,0 +1,516 @@
+//! An opinionated formatter for Lox source.
+//!
+//! Formatting works on the lossless syntax tree so that comments survive. The
+//! tree is first turned into a `Doc` describing where lines may break, and the
+//! doc is then laid out to fit the configured width where it can.
+
+use crate::scan::TokenType;
+use crate::syntax::{self, NodeKind, SyntaxElement, SyntaxNode, SyntaxToken};
+use std::collections::VecDeque;
+
+const INDENT: usize = 4;
+
+pub struct Config {
+    pub width: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self { width: 80 }
+    }
+}
+
+/// Formats a whole program, or returns the syntax errors that stopped it
+/// from being formatted.
+pub fn format(source: String, config: &Config) -> Result<String, Vec<syntax::Error>> {
+    let tree = syntax::parse(source);
+    if !tree.errors().is_empty() {
+        return Err(tree.into_errors());
+    }
+    Ok(render(&node(&tree.root()), config.width))
+}
+
+enum Doc {
+    Nil,
+    Text(String),
+    // takes no room when deciding whether a group fits, since it is always
+    // followed by a hard line anyway
+    Comment(String),
+    // a space, or a newline if the enclosing group doesn't fit
+    Line,
+    // nothing, or a newline if the enclosing group doesn't fit
+    SoftLine,
+    HardLine,
+    Indent(Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+fn text(s: &str) -> Doc {
+    Doc::Text(s.to_string())
+}
+
+enum Piece {
+    Comment { text: String, newlines: usize },
+    Node { node: SyntaxNode, newlines: usize },
+    Token(SyntaxToken),
+}
+
+// the children of a node with whitespace folded into a count of the newlines
+// that came before each remaining piece
+fn pieces(node: &SyntaxNode) -> VecDeque<Piece> {
+    let mut pieces = VecDeque::new();
+    let mut newlines = 0;
+    for child in node.children() {
+        match child {
+            SyntaxElement::Node(node) => {
+                pieces.push_back(Piece::Node { node, newlines });
+            }
+            SyntaxElement::Token(token) => match token.token_type() {
+                Some(TokenType::Whitespace(s)) => {
+                    newlines += s.matches('\n').count();
+                    continue;
+                }
+                Some(TokenType::Comment(s)) => {
+                    pieces.push_back(Piece::Comment {
+                        text: s.clone(),
+                        newlines,
+                    });
+                }
+                _ => {
+                    pieces.push_back(Piece::Token(token));
+                }
+            },
+        }
+        newlines = 0;
+    }
+    pieces
+}
+
+struct Cursor {
+    pieces: VecDeque<Piece>,
+}
+
+impl Cursor {
+    fn new(node: &SyntaxNode) -> Self {
+        Self {
+            pieces: pieces(node),
+        }
+    }
+
+    fn peek(&self) -> Option<&Piece> {
+        self.pieces
+            .iter()
+            .find(|piece| !matches!(piece, Piece::Comment { .. }))
+    }
+
+    fn peek_is(&self, type_: &TokenType) -> bool {
+        matches!(self.peek(), Some(Piece::Token(token)) if token.token_type() == Some(type_))
+    }
+
+    fn has_next(&self) -> bool {
+        self.peek().is_some()
+    }
+
+    // the next node or token, preceded by the separator or, if there are
+    // comments in the way, by the comments instead
+    fn next(&mut self, separator: Doc) -> Doc {
+        let mut comments = Vec::new();
+        while let Some(Piece::Comment { text, newlines }) = self.pieces.front() {
+            comments.push((text.clone(), *newlines));
+            self.pieces.pop_front();
+        }
+        let element = match self.pieces.pop_front() {
+            Some(Piece::Node { node: child, .. }) => node(&child),
+            Some(Piece::Token(token)) => text(token.text()),
+            _ => Doc::Nil,
+        };
+        if comments.is_empty() {
+            Doc::Concat(vec![separator, element])
+        } else {
+            Doc::Concat(vec![comment_run(comments), element])
+        }
+    }
+}
+
+fn comment_run(comments: Vec<(String, usize)>) -> Doc {
+    let mut docs = Vec::new();
+    for (i, (comment, newlines)) in comments.into_iter().enumerate() {
+        if i == 0 && newlines == 0 {
+            docs.push(Doc::Comment(format!(" {}", comment)));
+        } else {
+            docs.push(Doc::HardLine);
+            docs.push(Doc::Comment(comment));
+        }
+    }
+    docs.push(Doc::HardLine);
+    Doc::Concat(docs)
+}
+
+fn node(node: &SyntaxNode) -> Doc {
+    match node.kind() {
+        NodeKind::Program => {
+            let mut docs = statements(pieces(node), false);
+            if !docs.is_empty() {
+                docs.push(Doc::HardLine);
+            }
+            Doc::Concat(docs)
+        }
+        NodeKind::Block => block(node),
+        NodeKind::Var
+        | NodeKind::Print
+        | NodeKind::ExpressionStatement
+        | NodeKind::Assign
+        | NodeKind::Error => spaced(node),
+        NodeKind::Variable | NodeKind::Literal | NodeKind::Unary => {
+            let mut cursor = Cursor::new(node);
+            let mut docs = Vec::new();
+            while cursor.has_next() {
+                docs.push(cursor.next(Doc::Nil));
+            }
+            Doc::Concat(docs)
+        }
+        NodeKind::Grouping => {
+            let mut cursor = Cursor::new(node);
+            Doc::Group(Box::new(Doc::Concat(vec![
+                cursor.next(Doc::Nil),
+                Doc::Indent(Box::new(cursor.next(Doc::SoftLine))),
+                cursor.next(Doc::SoftLine),
+            ])))
+        }
+        NodeKind::Call => call(node),
+        NodeKind::Binary => {
+            let mut rest = Vec::new();
+            let head = binary(node, &mut rest);
+            Doc::Group(Box::new(Doc::Concat(vec![
+                head,
+                Doc::Indent(Box::new(Doc::Concat(rest))),
+            ])))
+        }
+    }
+}
+
+// elements separated by single spaces, except before a semicolon
+fn spaced(node: &SyntaxNode) -> Doc {
+    let mut cursor = Cursor::new(node);
+    let mut docs = Vec::new();
+    while cursor.has_next() {
+        let separator = if docs.is_empty() || cursor.peek_is(&TokenType::Semicolon) {
+            Doc::Nil
+        } else {
+            text(" ")
+        };
+        docs.push(cursor.next(separator));
+    }
+    Doc::Concat(docs)
+}
+
+fn precedence(node: &SyntaxNode) -> Option<u8> {
+    node.children().iter().find_map(|child| match child {
+        SyntaxElement::Token(token) => match token.token_type()? {
+            TokenType::EqualEqual | TokenType::BangEqual => Some(0),
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => Some(1),
+            TokenType::Minus | TokenType::Plus => Some(2),
+            TokenType::Slash | TokenType::Star => Some(3),
+            _ => None,
+        },
+        SyntaxElement::Node(_) => None,
+    })
+}
+
+// Chains of operators at the same precedence, like `a + b - c`, are laid out
+// as one group so they break at every operator or none of them.
+fn binary(node: &SyntaxNode, rest: &mut Vec<Doc>) -> Doc {
+    let mut cursor = Cursor::new(node);
+    let head = match cursor.pieces.front() {
+        Some(Piece::Node { node: left, .. })
+            if left.kind() == NodeKind::Binary && precedence(left) == precedence(node) =>
+        {
+            let left = left.clone();
+            cursor.pieces.pop_front();
+            binary(&left, rest)
+        }
+        _ => cursor.next(Doc::Nil),
+    };
+    rest.push(cursor.next(text(" ")));
+    rest.push(cursor.next(Doc::Line));
+    head
+}
+
+// Arguments stay on the callee's line if they fit, otherwise each gets a
+// line of its own.
+fn call(node: &SyntaxNode) -> Doc {
+    let mut cursor = Cursor::new(node);
+    let callee = cursor.next(Doc::Nil);
+    let open = cursor.next(Doc::Nil);
+    let mut arguments = Vec::new();
+    while cursor.has_next() && !cursor.peek_is(&TokenType::RightParen) {
+        let separator = if cursor.peek_is(&TokenType::Comma) {
+            Doc::Nil
+        } else if arguments.is_empty() {
+            Doc::SoftLine
+        } else {
+            Doc::Line
+        };
+        arguments.push(cursor.next(separator));
+    }
+    let close = if arguments.is_empty() {
+        cursor.next(Doc::Nil)
+    } else {
+        cursor.next(Doc::SoftLine)
+    };
+    Doc::Group(Box::new(Doc::Concat(vec![
+        callee,
+        open,
+        Doc::Indent(Box::new(Doc::Concat(arguments))),
+        close,
+    ])))
+}
+
+fn block(node: &SyntaxNode) -> Doc {
+    let mut pieces = pieces(node);
+    let open = match pieces.pop_front() {
+        Some(Piece::Token(token)) => text(token.text()),
+        _ => Doc::Nil,
+    };
+    let close = match pieces.pop_back() {
+        Some(Piece::Token(token)) => text(token.text()),
+        _ => Doc::Nil,
+    };
+    let inner = statements(pieces, true);
+    if inner.is_empty() {
+        return Doc::Concat(vec![open, close]);
+    }
+    Doc::Concat(vec![
+        open,
+        Doc::Indent(Box::new(Doc::Concat(inner))),
+        Doc::HardLine,
+        close,
+    ])
+}
+
+// Statements each get their own line, keeping at most one blank line between
+// them. A comment stays at the end of the line it was on, or on a line of its
+// own if it had one.
+fn statements(pieces: VecDeque<Piece>, mut line_started: bool) -> Vec<Doc> {
+    let mut docs = Vec::new();
+    for piece in pieces {
+        let (doc, newlines) = match piece {
+            Piece::Comment { text, newlines } => {
+                if line_started && newlines == 0 {
+                    docs.push(Doc::Comment(format!(" {}", text)));
+                    continue;
+                }
+                (Doc::Comment(text), newlines)
+            }
+            Piece::Node {
+                node: child,
+                newlines,
+            } => (node(&child), newlines),
+            Piece::Token(_) => continue,
+        };
+        if line_started {
+            if newlines > 1 && !docs.is_empty() {
+                docs.push(Doc::HardLine);
+            }
+            docs.push(Doc::HardLine);
+        }
+        docs.push(doc);
+        line_started = true;
+    }
+    docs
+}
+
+fn render(doc: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    let mut stack = vec![(0, false, doc)];
+    while let Some((indent, flat, doc)) = stack.pop() {
+        match doc {
+            Doc::Nil => {}
+            Doc::Text(s) | Doc::Comment(s) => {
+                out.push_str(s);
+                column += s.chars().count();
+            }
+            Doc::Line if flat => {
+                out.push(' ');
+                column += 1;
+            }
+            Doc::SoftLine if flat => {}
+            Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                while out.ends_with(' ') {
+                    out.pop();
+                }
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+                column = indent;
+            }
+            Doc::Indent(doc) => stack.push((indent + INDENT, flat, doc)),
+            Doc::Group(doc) => {
+                let remaining = width as isize - column as isize;
+                let flat = flat || fits(remaining, (indent, true, doc), &stack);
+                stack.push((indent, flat, doc));
+            }
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((indent, flat, doc));
+                }
+            }
+        }
+    }
+    out
+}
+
+// whether everything up to the next line break fits in the remaining width
+// if the candidate is laid out flat
+fn fits(
+    mut remaining: isize,
+    candidate: (usize, bool, &Doc),
+    rest: &[(usize, bool, &Doc)],
+) -> bool {
+    let mut queue = vec![candidate];
+    let mut rest = rest.iter().rev();
+    loop {
+        if remaining < 0 {
+            return false;
+        }
+        let (indent, flat, doc) = match queue.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some(next) => *next,
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Nil | Doc::Comment(_) => {}
+            Doc::Text(s) => remaining -= s.chars().count() as isize,
+            Doc::Line if flat => remaining -= 1,
+            Doc::SoftLine if flat => {}
+            // a hard line can't be laid out flat, so a group holding one breaks
+            Doc::HardLine if flat => return false,
+            Doc::Line | Doc::SoftLine | Doc::HardLine => return true,
+            Doc::Indent(doc) | Doc::Group(doc) => queue.push((indent, flat, doc)),
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    queue.push((indent, flat, doc));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formatted(source: &str) -> String {
+        format(source.to_string(), &Config::default()).unwrap()
+    }
+
+    fn narrow(source: &str, width: usize) -> String {
+        format(source.to_string(), &Config { width }).unwrap()
+    }
+
+    #[test]
+    fn test_spacing() {
+        assert_eq!("print 1 + 2 * -3;\n", formatted("print   1+2*  - 3 ;"));
+        assert_eq!("var a = (1 + 2);\n", formatted("var a=( 1+2 );"));
+        assert_eq!("a = b = !true;\n", formatted("a=b=!true;"));
+        assert_eq!("var a;\n", formatted("var\ta\n;"));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!("", formatted(""));
+        assert_eq!("", formatted("\n\n  \n"));
+    }
+
+    #[test]
+    fn test_statements_on_their_own_lines() {
+        assert_eq!("var a = 1;\nprint a;\n", formatted("var a = 1; print a;"));
+    }
+
+    #[test]
+    fn test_blank_lines_collapse() {
+        assert_eq!(
+            "var a = 1;\n\nprint a;\n",
+            formatted("\n\nvar a = 1;\n\n\n\nprint a;\n\n")
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        assert_eq!("{}\n", formatted("{ }"));
+        assert_eq!(
+            "{\n    var a = 1;\n    {\n        print a;\n    }\n}\n",
+            formatted("{var a = 1; {print a;}}")
+        );
+        assert_eq!(
+            "{\n    var a = 1;\n\n    print a;\n}\n",
+            formatted("{\n\n var a = 1;\n\n print a;\n\n}")
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            "// header\n\nvar a = 1; // one\n// about b\nvar b = 2;\n",
+            formatted("// header\n\nvar a = 1;   // one\n   // about b\nvar b = 2;")
+        );
+        assert_eq!(
+            "{ // open\n    print 1;\n    // close\n}\n",
+            formatted("{ // open\nprint 1;\n// close\n}")
+        );
+        assert_eq!(
+            "print 1 + // one\n    2;\n",
+            formatted("print 1 + // one\n2;")
+        );
+    }
+
+    #[test]
+    fn test_wrapping() {
+        assert_eq!(
+            "print aaaa +\n    bbbb -\n    cccc;\n",
+            narrow("print aaaa + bbbb - cccc;", 16)
+        );
+        assert_eq!(
+            "print aaaa * bbbb +\n    cccc;\n",
+            narrow("print aaaa * bbbb + cccc;", 20)
+        );
+        assert_eq!(
+            "print (\n    aaaa + bbbb\n);\n",
+            narrow("print (aaaa + bbbb);", 16)
+        );
+        assert_eq!(
+            "print aaaa + bbbb + cccc;\n",
+            narrow("print aaaa +\n bbbb +\n cccc;", 80)
+        );
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!("print f();\n", formatted("print f ( );"));
+        assert_eq!("f(1, g(2))(a);\n", formatted("f(1,g( 2 ))( a );"));
+        assert_eq!(
+            "print fff(\n    aaaa,\n    bbbb\n);\n",
+            narrow("print fff(aaaa, bbbb);", 16)
+        );
+    }
+
+    #[test]
+    fn test_preserves_literals() {
+        assert_eq!(
+            "print 1.50 + \"a  b\";\n",
+            formatted("print 1.50+\"a  b\";")
+        );
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert!(format("print 1".to_string(), &Config::default()).is_err());
+        assert!(format("1 & 2;".to_string(), &Config::default()).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+//! Golden-file tests: Lox scripts annotated with what running them should
+//! do, in the style of the Crafting Interpreters test suite.
+//!
+//! - `// expect: TEXT` means the next line printed is TEXT
+//! - `// expect runtime error: MESSAGE` means running stops with MESSAGE on
+//!   this line
+//! - `// Error...` means a compile error on this line, reported as
+//!   `[line N] Error...`
+//! - `// [line N] Error...` means a compile error on line N
+//!
+//! Each script runs in its own interpreter process, so a crash or a hang is
+//! only that script's failure: one still running after `TIMEOUT` is killed.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a script gets before it counts as hung.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Expectations {
+    pub output: Vec<String>,
+    /// The message and the line it's reported on.
+    pub runtime_error: Option<(String, usize)>,
+    pub compile_errors: Vec<String>,
+}
+
+pub fn expectations(source: &str) -> Expectations {
+    let mut expectations = Expectations::default();
+    for (i, line) in source.lines().enumerate() {
+        let number = i + 1;
+        if let Some((_, text)) = line.split_once("// expect: ") {
+            expectations.output.push(text.to_string());
+        } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+            expectations.runtime_error = Some((message.to_string(), number));
+        } else if let Some((_, error)) = line.split_once("// [line ") {
+            expectations.compile_errors.push(format!("[line {}", error));
+        } else if let Some((_, error)) = line.split_once("// Error") {
+            expectations
+                .compile_errors
+                .push(format!("[line {}] Error{}", number, error));
+        }
+    }
+    expectations
+}
+
+/// What running a script did.
+pub struct Outcome {
+    pub stdout: String,
+    pub stderr: String,
+    /// None if the process was killed by a signal.
+    pub status: Option<i32>,
+}
+
+/// Every way the outcome differs from what was expected, so an empty list is a
+/// pass. Compile errors exit with 65 and runtime errors with 70, as the
+/// interpreter does.
+pub fn compare(expectations: &Expectations, outcome: &Outcome) -> Vec<String> {
+    let (status, stderr) = if !expectations.compile_errors.is_empty() {
+        (65, expectations.compile_errors.clone())
+    } else if let Some((message, line)) = &expectations.runtime_error {
+        (70, vec![message.clone(), format!("[line {}]", line)])
+    } else {
+        (0, vec![])
+    };
+
+    let mut failures = Vec::new();
+    let actual_stdout: Vec<String> = outcome.stdout.lines().map(String::from).collect();
+    if actual_stdout != expectations.output {
+        failures.push(format!(
+            "output differs:\n{}",
+            diff(&expectations.output, &actual_stdout)
+        ));
+    }
+    let actual_stderr: Vec<String> = outcome.stderr.lines().map(String::from).collect();
+    if actual_stderr != stderr {
+        failures.push(format!("errors differ:\n{}", diff(&stderr, &actual_stderr)));
+    }
+    match outcome.status {
+        Some(actual) if actual == status => {}
+        Some(actual) => failures.push(format!(
+            "expected exit status {} but got {}",
+            status, actual
+        )),
+        None => failures.push(format!("expected exit status {} but it was killed", status)),
+    }
+    failures
+}
+
+/// A line diff, `-` marking lines that were expected but missing and `+`
+/// lines that shouldn't be there.
+pub fn diff(expected: &[String], actual: &[String]) -> String {
+    // common[i][j] is the length of the longest common subsequence of
+    // expected[i..] and actual[j..]
+    let mut common = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || common[i + 1][j] >= common[i][j + 1])
+        {
+            lines.push(format!("- {}", expected[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", actual[j]));
+            j += 1;
+        }
+    }
+    lines.join("\n")
+}
+
+/// Runs one script with the interpreter at `program` and checks it against
+/// its annotations.
+pub fn test(program: &Path, script: &Path) -> Vec<String> {
+    test_with_timeout(program, script, TIMEOUT)
+}
+
+pub fn test_with_timeout(program: &Path, script: &Path, timeout: Duration) -> Vec<String> {
+    let source = match std::fs::read_to_string(script) {
+        Ok(source) => source,
+        Err(error) => return vec![format!("couldn't read the script: {}", error)],
+    };
+    match run(program, script, timeout) {
+        Ok(Some(outcome)) => compare(&expectations(&source), &outcome),
+        Ok(None) => vec![format!("killed after running for {:?}", timeout)],
+        Err(error) => vec![format!("couldn't run the interpreter: {}", error)],
+    }
+}
+
+/// None if the script was still running after `timeout`.
+fn run(program: &Path, script: &Path, timeout: Duration) -> std::io::Result<Option<Outcome>> {
+    let mut child = Command::new(program)
+        .arg(script)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // read as it runs, so a full pipe can't stop it finishing
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+    Ok(Some(Outcome {
+        stdout: String::from_utf8_lossy(&stdout.join().unwrap_or_default()).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned(),
+        status: status.code(),
+    }))
+}
+
+fn read_all(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+/// Tests every script, as many at once as there are cores, returning each
+/// script's failures in the order the scripts were given.
+pub fn test_all(program: &Path, scripts: Vec<PathBuf>) -> Vec<(PathBuf, Vec<String>)> {
+    let jobs = thread::available_parallelism().map_or(1, |jobs| jobs.get());
+    let queue = Mutex::new(scripts.into_iter().enumerate());
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some((i, script)) => {
+                        let failures = test(program, &script);
+                        results.lock().unwrap().push((i, script, failures));
+                    }
+                    None => break,
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _, _)| *i);
+    results
+        .into_iter()
+        .map(|(_, script, failures)| (script, failures))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    fn outcome(stdout: &str, stderr: &str, status: i32) -> Outcome {
+        Outcome {
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+            status: Some(status),
+        }
+    }
+
+    #[test]
+    fn test_expectations() {
+        let source = "\
+print 1; // expect: 1
+print \"a // b\"; // expect: a // b
+var a = -nil; // expect runtime error: Operand must be a number.
+print 1 +; // Error: Expect expression.
+// [line 7] Error: Expect ';'.
+";
+        assert_eq!(
+            Expectations {
+                output: lines(&["1", "a // b"]),
+                runtime_error: Some(("Operand must be a number.".to_string(), 3)),
+                compile_errors: lines(&[
+                    "[line 4] Error: Expect expression.",
+                    "[line 7] Error: Expect ';'."
+                ]),
+            },
+            expectations(source)
+        );
+    }
+
+    #[test]
+    fn test_compare_passes() {
+        let expected = expectations("print 1; // expect: 1\nprint 2; // expect: 2");
+        assert!(compare(&expected, &outcome("1\n2\n", "", 0)).is_empty());
+
+        let expected = expectations("-nil; // expect runtime error: Operand must be a number.");
+        assert!(compare(
+            &expected,
+            &outcome("", "Operand must be a number.\n[line 1]\n", 70)
+        )
+        .is_empty());
+
+        let expected = expectations("print; // Error: Expect expression.");
+        assert!(compare(
+            &expected,
+            &outcome("", "[line 1] Error: Expect expression.\n", 65)
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_compare_fails() {
+        let expected = expectations("print 1; // expect: 1\nprint 2; // expect: 2");
+        assert_eq!(
+            vec!["output differs:\n  1\n- 2\n+ 3".to_string()],
+            compare(&expected, &outcome("1\n3\n", "", 0))
+        );
+        assert_eq!(
+            vec![
+                "errors differ:\n+ oops".to_string(),
+                "expected exit status 0 but got 70".to_string()
+            ],
+            compare(&expected, &outcome("1\n2\n", "oops\n", 70))
+        );
+        let killed = Outcome {
+            stdout: "1\n2\n".to_string(),
+            stderr: String::new(),
+            status: None,
+        };
+        assert_eq!(
+            vec!["expected exit status 0 but it was killed".to_string()],
+            compare(&expected, &killed)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hang_is_killed() {
+        let script = std::env::temp_dir().join(format!("hang-{}.sh", std::process::id()));
+        std::fs::write(&script, "sleep 10\n").unwrap();
+        let start = Instant::now();
+        let failures = test_with_timeout(Path::new("sh"), &script, Duration::from_millis(100));
+        std::fs::remove_file(&script).unwrap();
+        assert_eq!(vec!["killed after running for 100ms".to_string()], failures);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let script = std::env::temp_dir().join(format!("quick-{}.sh", std::process::id()));
+        std::fs::write(&script, "echo 1 # // expect: 1\n").unwrap();
+        let failures = test_with_timeout(Path::new("sh"), &script, Duration::from_secs(5));
+        std::fs::remove_file(&script).unwrap();
+        assert_eq!(Vec::<String>::new(), failures);
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = lines(&["a", "b", "c", "d"]);
+        let after = lines(&["a", "c", "x", "d", "e"]);
+        assert_eq!("  a\n- b\n  c\n+ x\n  d\n+ e", diff(&before, &after));
+        assert_eq!("- a\n- b", diff(&lines(&["a", "b"]), &[]));
+        assert_eq!("", diff(&[], &[]));
+    }
+}
@@ -0,0 +1,820 @@
+use crate::check::{self, check};
+use crate::expression::{Expression, Value};
+use crate::native::{self, IntoLox, Native};
+use crate::parse::{parse_expression, parse_program};
+use crate::scan::{scan_tokens, Token, TokenType};
+use crate::statement::Statement;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Write};
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    OperandMustBeNumber {
+        line: usize,
+    },
+    OperandsMustBeNumbers {
+        line: usize,
+    },
+    OperandsMustBeNumbersOrStrings {
+        line: usize,
+    },
+    UndefinedVariable {
+        name: String,
+        line: usize,
+    },
+    NotCallable {
+        line: usize,
+    },
+    WrongArity {
+        expected: usize,
+        got: usize,
+        line: usize,
+    },
+    /// What a native function returned as its error.
+    Native {
+        message: String,
+        line: usize,
+    },
+    /// `print` couldn't write to the output.
+    Output {
+        kind: ErrorKind,
+    },
+}
+
+impl Error {
+    /// None if the error isn't down to any one line of the script.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::OperandMustBeNumber { line }
+            | Error::OperandsMustBeNumbers { line }
+            | Error::OperandsMustBeNumbersOrStrings { line }
+            | Error::UndefinedVariable { line, .. }
+            | Error::NotCallable { line }
+            | Error::WrongArity { line, .. }
+            | Error::Native { line, .. } => Some(*line),
+            Error::Output { .. } => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::OperandMustBeNumber { .. } => write!(f, "Operand must be a number."),
+            Error::OperandsMustBeNumbers { .. } => write!(f, "Operands must be numbers."),
+            Error::OperandsMustBeNumbersOrStrings { .. } => {
+                write!(f, "Operands must be two numbers or two strings.")
+            }
+            Error::UndefinedVariable { name, .. } => write!(f, "Undefined variable '{}'.", name),
+            Error::NotCallable { .. } => write!(f, "Can only call functions and classes."),
+            Error::WrongArity { expected, got, .. } => {
+                write!(f, "Expected {} arguments but got {}.", expected, got)
+            }
+            Error::Native { message, .. } => write!(f, "{}", message),
+            Error::Output { kind } => write!(f, "Couldn't write output: {}.", kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Why running some Lox from the host failed: everything wrong with it found
+/// before running, or the error that stopped it.
+#[derive(Debug, PartialEq)]
+pub enum LoxError {
+    Static(Vec<check::Error>),
+    Runtime(Error),
+}
+
+impl std::fmt::Display for LoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoxError::Static(errors) => {
+                let lines: Vec<String> = errors
+                    .iter()
+                    .map(|error| format!("[line {}] Error: {}", error.line() + 1, error))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            LoxError::Runtime(error) => match error.line() {
+                Some(line) => write!(f, "{}\n[line {}]", error, line + 1),
+                None => write!(f, "{}", error),
+            },
+        }
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+impl From<Error> for LoxError {
+    fn from(error: Error) -> Self {
+        LoxError::Runtime(error)
+    }
+}
+
+/// Variables, innermost scope last. The first scope holds the globals and is
+/// never popped.
+pub struct Environment {
+    scopes: Vec<HashMap<String, Value>>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    pub fn define(&mut self, name: String, value: Value) {
+        self.scopes.last_mut().unwrap().insert(name, value);
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, Error> {
+        let key = name.to_string();
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&key))
+            .cloned()
+            .ok_or(Error::UndefinedVariable {
+                name: key,
+                line: name.line(),
+            })
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), Error> {
+        let key = name.to_string();
+        match self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&key))
+        {
+            Some(variable) => {
+                *variable = value;
+                Ok(())
+            }
+            None => Err(Error::UndefinedVariable {
+                name: key,
+                line: name.line(),
+            }),
+        }
+    }
+
+    /// The global variables, sorted by name.
+    pub fn globals(&self) -> Vec<(&str, &Value)> {
+        let mut globals: Vec<_> = self.scopes[0]
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(b.0));
+        globals
+    }
+
+    fn push(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+}
+
+/// Runs statements, keeping variables from one call to the next. `print`
+/// writes to the output, and whoever runs the interpreter reports errors to
+/// the diagnostics.
+pub struct Interpreter {
+    environment: Environment,
+    output: Box<dyn Write>,
+    diagnostics: Box<dyn Write>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    /// Writes to stdout, with diagnostics on stderr.
+    pub fn new() -> Self {
+        Self::with_output(std::io::stdout(), std::io::stderr())
+    }
+
+    pub fn with_output(output: impl Write + 'static, diagnostics: impl Write + 'static) -> Self {
+        let mut interpreter = Self {
+            environment: Environment::new(),
+            output: Box::new(output),
+            diagnostics: Box::new(diagnostics),
+        };
+        interpreter.set_global("clock", Value::Native(Rc::new(native::clock())));
+        interpreter
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
+    }
+
+    pub fn diagnostics(&mut self) -> &mut dyn Write {
+        &mut self.diagnostics
+    }
+
+    /// Forgets every variable except the native functions, keeping the output
+    /// and diagnostics.
+    pub fn reset(&mut self) {
+        let globals = std::mem::take(&mut self.environment.scopes[0]);
+        self.environment = Environment::new();
+        self.environment.scopes[0].extend(
+            globals
+                .into_iter()
+                .filter(|(_, value)| matches!(value, Value::Native(_))),
+        );
+    }
+
+    /// Binds a variable in the outermost scope, as a `var` at the top level
+    /// of a script would.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.environment.scopes[0].insert(name.to_string(), value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<&Value> {
+        self.environment.scopes[0].get(name)
+    }
+
+    /// Makes a Rust function callable from Lox as a global. It's only called
+    /// with `arity` arguments, which `FromLox` can convert, and an error it
+    /// returns stops the script as a runtime error at the call.
+    pub fn register_fn<R: IntoLox>(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<R, String> + 'static,
+    ) {
+        let native = Native::new(name, arity, move |arguments| {
+            function(arguments).map(IntoLox::into_lox)
+        });
+        self.set_global(name, Value::Native(Rc::new(native)));
+    }
+
+    /// Checks and runs a script, returning the value of its last statement if
+    /// that's an expression and nil otherwise. Nothing runs if anything is
+    /// found wrong first.
+    pub fn run_source(&mut self, source: &str) -> Result<Value, LoxError> {
+        let errors = check(source.to_string());
+        if !errors.is_empty() {
+            return Err(LoxError::Static(errors));
+        }
+        // having checked, neither of these can fail
+        let tokens = scan_tokens(source.to_string()).expect("checked");
+        let statements = parse_program(VecDeque::from(tokens)).expect("checked");
+        Ok(self.run(&statements)?)
+    }
+
+    /// Evaluates a single expression, with no semicolon, against the globals.
+    pub fn eval_expression(&mut self, source: &str) -> Result<Value, LoxError> {
+        let tokens = scan_tokens(source.to_string())
+            .map_err(|error| LoxError::Static(vec![check::Error::Scan(error)]))?;
+        let expression = parse_expression(VecDeque::from(tokens))
+            .map_err(|(error, line)| LoxError::Static(vec![check::Error::Parse { error, line }]))?;
+        Ok(self.evaluate(&expression)?)
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn execute(&mut self, statements: &[Statement]) -> Result<(), Error> {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    /// Like execute, but evaluates a last expression statement for its value.
+    pub fn run(&mut self, statements: &[Statement]) -> Result<Value, Error> {
+        match statements.split_last() {
+            Some((Statement::Expression { expression }, rest)) => {
+                self.execute(rest)?;
+                self.evaluate(expression)
+            }
+            _ => {
+                self.execute(statements)?;
+                Ok(Value::Nil)
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), Error> {
+        match statement {
+            Statement::Expression { expression } => {
+                self.evaluate(expression)?;
+            }
+            Statement::Print { expression } => {
+                let value = self.evaluate(expression)?;
+                writeln!(self.output, "{}", value)
+                    .map_err(|error| Error::Output { kind: error.kind() })?;
+            }
+            Statement::Var { name, initializer } => {
+                let value = match initializer {
+                    Some(initializer) => self.evaluate(initializer)?,
+                    None => Value::Nil,
+                };
+                self.environment.define(name.to_string(), value);
+            }
+            Statement::Block { statements } => {
+                self.environment.push();
+                let result = self.execute(statements);
+                self.environment.pop();
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn evaluate(&mut self, expression: &Expression) -> Result<Value, Error> {
+        match expression {
+            Expression::Literal { value, .. } => Ok(value.clone()),
+            Expression::Grouping { expression } => self.evaluate(expression),
+            Expression::Unary {
+                operator,
+                expression,
+            } => {
+                let value = self.evaluate(expression)?;
+                unary(operator, &value)
+            }
+            Expression::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                binary(&left, operator, &right)
+            }
+            Expression::Variable { name } => self.environment.get(name),
+            Expression::Assign { name, value } => {
+                let value = self.evaluate(value)?;
+                self.environment.assign(name, value.clone())?;
+                Ok(value)
+            }
+            Expression::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                let callee = self.evaluate(callee)?;
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.evaluate(argument))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let line = paren.line();
+                let native = match callee {
+                    Value::Native(native) => native,
+                    _ => return Err(Error::NotCallable { line }),
+                };
+                if arguments.len() != native.arity() {
+                    return Err(Error::WrongArity {
+                        expected: native.arity(),
+                        got: arguments.len(),
+                        line,
+                    });
+                }
+                native
+                    .call(&arguments)
+                    .map_err(|message| Error::Native { message, line })
+            }
+        }
+    }
+}
+
+/// An output that can be read back after the interpreter has written to it,
+/// for capturing what a script prints.
+#[derive(Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn is_truthy(value: &Value) -> bool {
+    !matches!(value, Value::False | Value::Nil)
+}
+
+fn boolean(b: bool) -> Value {
+    if b {
+        Value::True
+    } else {
+        Value::False
+    }
+}
+
+/// The value of `operator value`.
+pub(crate) fn unary(operator: &Token, value: &Value) -> Result<Value, Error> {
+    match (operator.token_type(), value) {
+        (TokenType::Minus, Value::Float(n)) => Ok(Value::Float(-n)),
+        (TokenType::Bang, value) => Ok(boolean(!is_truthy(value))),
+        _ => Err(Error::OperandMustBeNumber {
+            line: operator.line(),
+        }),
+    }
+}
+
+/// The value of `left operator right`.
+pub(crate) fn binary(left: &Value, operator: &Token, right: &Value) -> Result<Value, Error> {
+    let line = operator.line();
+    let value = match (left, operator.token_type(), right) {
+        (left, TokenType::EqualEqual, right) => boolean(left == right),
+        (left, TokenType::BangEqual, right) => boolean(left != right),
+        (Value::Str(a), TokenType::Plus, Value::Str(b)) => Value::Str(format!("{}{}", a, b)),
+        (Value::Float(a), type_, Value::Float(b)) => match type_ {
+            TokenType::Plus => Value::Float(a + b),
+            TokenType::Minus => Value::Float(a - b),
+            TokenType::Star => Value::Float(a * b),
+            TokenType::Slash => Value::Float(a / b),
+            TokenType::Greater => boolean(a > b),
+            TokenType::GreaterEqual => boolean(a >= b),
+            TokenType::Less => boolean(a < b),
+            _ => boolean(a <= b),
+        },
+        (_, TokenType::Plus, _) => return Err(Error::OperandsMustBeNumbersOrStrings { line }),
+        _ => return Err(Error::OperandsMustBeNumbers { line }),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::FromLox;
+
+    fn program(source: &str) -> Vec<Statement> {
+        parse_program(VecDeque::from(scan_tokens(source.to_string()).unwrap())).unwrap()
+    }
+
+    fn identifier(name: &str) -> Token {
+        Token::new(TokenType::Identifier(name.to_string()), 0)
+    }
+
+    fn evaluated(source: &str) -> Result<Value, Error> {
+        let mut interpreter = Interpreter::new();
+        match &program(&format!("{};", source))[..] {
+            [Statement::Expression { expression }] => interpreter.evaluate(expression),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(Ok(Value::Float(7.0)), evaluated("1 + 2 * 3"));
+        assert_eq!(Ok(Value::Float(-1.0)), evaluated("(1 - 2) / 1"));
+        assert_eq!(Ok(Value::Float(f32::INFINITY)), evaluated("1 / 0"));
+        assert_eq!(Ok(Value::Str("ab".to_string())), evaluated("\"a\" + \"b\""));
+    }
+
+    #[test]
+    fn test_logic() {
+        assert_eq!(Ok(Value::True), evaluated("1 < 2"));
+        assert_eq!(Ok(Value::True), evaluated("!nil"));
+        assert_eq!(Ok(Value::False), evaluated("!0"));
+        assert_eq!(Ok(Value::True), evaluated("nil == nil"));
+        assert_eq!(Ok(Value::False), evaluated("\"1\" == 1"));
+        assert_eq!(Ok(Value::True), evaluated("2 >= 2 != false"));
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        assert_eq!(
+            Err(Error::OperandMustBeNumber { line: 0 }),
+            evaluated("-\"a\"")
+        );
+        assert_eq!(
+            Err(Error::OperandsMustBeNumbers { line: 1 }),
+            evaluated("1\n < true")
+        );
+        assert_eq!(
+            Err(Error::OperandsMustBeNumbersOrStrings { line: 0 }),
+            evaluated("1 + \"a\"")
+        );
+        assert_eq!(
+            "Operands must be two numbers or two strings.",
+            evaluated("nil + nil").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .execute(&program("var a = 1; var b; a = a + 1;"))
+            .unwrap();
+        assert_eq!(
+            Ok(Value::Float(2.0)),
+            interpreter.environment.get(&identifier("a"))
+        );
+        assert_eq!(
+            Ok(Value::Nil),
+            interpreter.environment.get(&identifier("b"))
+        );
+
+        // state persists from one call to the next
+        interpreter.execute(&program("b = a * 3;")).unwrap();
+        assert_eq!(
+            Ok(Value::Float(6.0)),
+            interpreter.environment.get(&identifier("b"))
+        );
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .execute(&program("var b = 2; var a; { var c = 3; }"))
+            .unwrap();
+        let globals = interpreter.environment().globals();
+        assert_eq!(
+            vec![("a", &Value::Nil), ("b", &Value::Float(2.0))],
+            globals[..2]
+        );
+        // natives are globals too
+        assert_eq!("clock", globals[2].0);
+        assert_eq!(3, globals.len());
+    }
+
+    #[test]
+    fn test_define() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("args", Value::List(vec![Value::Str("-v".to_string())]));
+        interpreter.execute(&program("var a = args;")).unwrap();
+        assert_eq!(
+            Ok(Value::List(vec![Value::Str("-v".to_string())])),
+            interpreter.environment.get(&identifier("a"))
+        );
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            Err(Error::UndefinedVariable {
+                name: "a".to_string(),
+                line: 1
+            }),
+            interpreter.execute(&program("var b;\nb = a;"))
+        );
+        assert_eq!(
+            Err(Error::UndefinedVariable {
+                name: "c".to_string(),
+                line: 0
+            }),
+            interpreter.execute(&program("c = 1;"))
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .execute(&program(
+                "var a = 1; var b = 1; { var a = 2; b = a; { a = 3; } }",
+            ))
+            .unwrap();
+        assert_eq!(
+            Ok(Value::Float(1.0)),
+            interpreter.environment.get(&identifier("a"))
+        );
+        assert_eq!(
+            Ok(Value::Float(2.0)),
+            interpreter.environment.get(&identifier("b"))
+        );
+    }
+
+    #[test]
+    fn test_block_scope_ends_on_error() {
+        let mut interpreter = Interpreter::new();
+        assert!(interpreter
+            .execute(&program("{ var inner = 1; -nil; }"))
+            .is_err());
+        assert!(interpreter.environment.get(&identifier("inner")).is_err());
+    }
+
+    #[test]
+    fn test_print_to_output() {
+        let output = Capture::default();
+        let diagnostics = Capture::default();
+        let mut interpreter = Interpreter::with_output(output.clone(), diagnostics.clone());
+        interpreter
+            .execute(&program("print 1 + 2; { var a = \"b\"; print a; }"))
+            .unwrap();
+        assert_eq!("3\nb\n", output.contents());
+        assert_eq!("", diagnostics.contents());
+
+        interpreter.reset();
+        interpreter.execute(&program("print nil;")).unwrap();
+        assert_eq!("3\nb\nnil\n", output.contents());
+        // natives survive a reset
+        let globals = interpreter.environment().globals();
+        assert_eq!(
+            vec!["clock"],
+            globals.iter().map(|(name, _)| *name).collect::<Vec<_>>()
+        );
+    }
+
+    struct Closed;
+
+    impl Write for Closed {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_output_error() {
+        let mut interpreter = Interpreter::with_output(Closed, Capture::default());
+        let error = interpreter.execute(&program("print 1;")).unwrap_err();
+        assert_eq!(
+            Error::Output {
+                kind: ErrorKind::BrokenPipe
+            },
+            error
+        );
+        assert_eq!(None, error.line());
+        assert_eq!("Couldn't write output: broken pipe.", error.to_string());
+    }
+
+    #[test]
+    fn test_run_source() {
+        let mut interpreter = Interpreter::with_output(Capture::default(), Capture::default());
+        assert_eq!(
+            Ok(Value::Float(3.0)),
+            interpreter.run_source("var a = 1;\na + 2;")
+        );
+        assert_eq!(Ok(Value::Nil), interpreter.run_source("a = 5; print a;"));
+        assert_eq!(Some(&Value::Float(5.0)), interpreter.get_global("a"));
+        assert_eq!(None, interpreter.get_global("b"));
+        assert_eq!(Ok(Value::Nil), interpreter.run_source(""));
+    }
+
+    #[test]
+    fn test_run_source_errors() {
+        let mut interpreter = Interpreter::with_output(Capture::default(), Capture::default());
+        let error = interpreter
+            .run_source("var a = 1;\nprint 1 +;\n{ var b = b; }")
+            .unwrap_err();
+        assert_eq!(
+            "[line 2] Error: Expect expression.\n\
+             [line 3] Error: Can't read local variable 'b' in its own initializer.",
+            error.to_string()
+        );
+        // nothing ran
+        assert_eq!(None, interpreter.get_global("a"));
+
+        assert_eq!(
+            Err(LoxError::Runtime(Error::OperandMustBeNumber { line: 1 })),
+            interpreter.run_source("var a = 1;\n-nil;")
+        );
+        // what ran before the error stays
+        assert_eq!(Some(&Value::Float(1.0)), interpreter.get_global("a"));
+        assert_eq!(
+            "Operand must be a number.\n[line 1]",
+            interpreter.run_source("-nil;").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_eval_expression() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("limit", Value::Float(10.0));
+        assert_eq!(
+            Ok(Value::True),
+            interpreter.eval_expression("limit * 2 > 15")
+        );
+        assert_eq!(
+            Ok(Value::Float(3.0)),
+            interpreter.eval_expression("limit = 3")
+        );
+        assert_eq!(Some(&Value::Float(3.0)), interpreter.get_global("limit"));
+        assert_eq!(
+            "[line 1] Error: Expect end of input.",
+            interpreter.eval_expression("1;").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "[line 1] Error: Unexpected character '#'.",
+            interpreter.eval_expression("#").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Undefined variable 'x'.\n[line 1]",
+            interpreter.eval_expression("x").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("add", 2, |arguments| {
+            Ok(f64::from_lox(&arguments[0])? + f64::from_lox(&arguments[1])?)
+        });
+        interpreter.register_fn("greet", 1, |arguments| {
+            let name = Option::<String>::from_lox(&arguments[0])?;
+            Ok(format!("hello {}", name.as_deref().unwrap_or("world")))
+        });
+        assert_eq!(
+            Ok(Value::Float(6.0)),
+            interpreter.eval_expression("add(1, add(2, 3))")
+        );
+        assert_eq!(
+            Ok(Value::Str("hello world".to_string())),
+            interpreter.eval_expression("greet(nil)")
+        );
+        assert_eq!(
+            Ok(Value::Str("hello lox".to_string())),
+            interpreter.run_source("var f = greet; f(\"lox\");")
+        );
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("half", 1, |arguments| {
+            let n = i64::from_lox(&arguments[0])?;
+            if n % 2 != 0 {
+                return Err(format!("{} is odd.", n));
+            }
+            Ok(n / 2)
+        });
+        assert_eq!(
+            Ok(Value::Float(2.0)),
+            interpreter.eval_expression("half(4)")
+        );
+        assert_eq!(
+            Err(LoxError::Runtime(Error::Native {
+                message: "3 is odd.".to_string(),
+                line: 2
+            })),
+            interpreter.run_source("half(4);\nhalf(\n3);")
+        );
+        assert_eq!(
+            "Expected an integer but got 'a'.\n[line 1]",
+            interpreter
+                .eval_expression("half(\"a\")")
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            Err(LoxError::Runtime(Error::WrongArity {
+                expected: 1,
+                got: 2,
+                line: 0
+            })),
+            interpreter.eval_expression("half(1, 2)")
+        );
+        assert_eq!(
+            "Can only call functions and classes.\n[line 1]",
+            interpreter
+                .eval_expression("\"half\"(2)")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            Ok(Value::True),
+            interpreter.eval_expression("clock() > 0 == (clock() >= 0)")
+        );
+        assert_eq!(
+            Ok(Value::Str("<native fn>".to_string())),
+            interpreter
+                .eval_expression("clock")
+                .map(|clock| Value::Str(clock.to_string()))
+        );
+    }
+}
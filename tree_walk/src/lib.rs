@@ -1,3 +1,17 @@
+pub mod check;
+pub mod emit;
 pub mod expression;
+pub mod format;
+pub mod golden;
+pub mod interpret;
+pub mod native;
+pub mod optimize;
 pub mod parse;
+pub mod repl;
+pub mod resolve;
 pub mod scan;
+pub mod statement;
+pub mod syntax;
+
+pub use interpret::{Interpreter, LoxError};
+pub use native::{FromLox, IntoLox};
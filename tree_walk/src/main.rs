@@ -1,72 +1,585 @@
-use clap::Parser;
+use clap::{ArgEnum, Parser, Subcommand};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
 use std::collections::VecDeque;
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{stdin, stdout};
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::io::stdin;
+use std::io::{BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use tree_walk::parse::parse;
+use tree_walk::check::check as lox_check;
+use tree_walk::emit;
+use tree_walk::expression::Value;
+use tree_walk::format::{format, Config};
+use tree_walk::golden;
+use tree_walk::interpret::{self, Interpreter};
+use tree_walk::optimize::optimize;
+use tree_walk::parse::{parse_program, parse_repl};
+use tree_walk::repl::{is_complete, parse_command, Command as ReplCommand, Completion, HELP};
+use tree_walk::resolve::resolve;
 use tree_walk::scan::scan_tokens;
+use tree_walk::statement::Statement;
 
 #[derive(Parser)]
-#[clap(version)]
+#[clap(version, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Lox script to execute
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Lox code to run instead of a script; every positional argument goes to `args`
+    #[clap(short, long)]
+    eval: Option<String>,
+
+    /// Lox script to execute, or - to read it from stdin
     script: Option<PathBuf>,
+
+    /// Passed to the script as the list `args`, even if they look like options
+    args: Vec<String>,
+
+    /// Print the scanner or parser output instead of running
+    #[clap(long, arg_enum)]
+    emit: Option<Emit>,
+
+    /// Fold constants and simplify expressions, reporting how many nodes were folded
+    #[clap(long)]
+    optimize: bool,
+
+    /// Write what the script prints to this file instead of stdout
+    #[clap(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(ArgEnum, Clone, Copy)]
+enum Emit {
+    Tokens,
+    AstSexpr,
+    AstJson,
+    AstDot,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Rewrite Lox scripts in the canonical style
+    Fmt(FmtArgs),
+    /// Report every error in Lox scripts without running them
+    Check(CheckArgs),
+    /// Run Lox scripts and compare what they do with their `// expect` comments
+    Test(TestArgs),
+}
+
+#[derive(clap::Args)]
+struct TestArgs {
+    /// Directory to search for .lox scripts
+    directory: PathBuf,
+
+    /// Only run scripts whose path contains this; may be given more than once
+    #[clap(long)]
+    filter: Vec<String>,
+}
+
+#[derive(clap::Args)]
+struct CheckArgs {
+    /// Scripts, or directories to search for .lox scripts
+    #[clap(required = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct FmtArgs {
+    /// Scripts to format in place; formats stdin to stdout if none are given
+    files: Vec<PathBuf>,
+
+    /// Don't write anything, just fail if any script isn't formatted
+    #[clap(long)]
+    check: bool,
+
+    /// Line width to wrap long statements at
+    #[clap(long, default_value_t = 80)]
+    width: usize,
 }
 
 fn main() {
-    let args = Args::parse();
-    let result = match args.script {
-        None => run_prompt(),
-        Some(script) => run_file(script),
+    let (argv, script_args) = split_script_args(std::env::args_os().collect());
+    let mut args = Args::parse_from(argv);
+    args.args.extend(script_args);
+    let result = match args.command {
+        Some(Command::Fmt(fmt_args)) => fmt(fmt_args),
+        Some(Command::Check(check_args)) => check(check_args),
+        Some(Command::Test(test_args)) => test(test_args),
+        None => match (args.eval, args.script) {
+            (Some(source), script) => {
+                let script_args = script
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .into_iter()
+                    .chain(args.args)
+                    .collect();
+                interpreter(script_args, args.output.as_deref())
+                    .map(|interpreter| run_script(interpreter, source, args.emit, args.optimize))
+            }
+            (None, Some(script)) => interpreter(args.args, args.output.as_deref())
+                .and_then(|interpreter| run_file(interpreter, script, args.emit, args.optimize)),
+            (None, None) => interpreter(vec![], args.output.as_deref())
+                .and_then(|interpreter| run_prompt(interpreter, args.emit, args.optimize)),
+        },
     };
     result.unwrap();
 }
 
-fn run_file(script: PathBuf) -> std::io::Result<()> {
-    let mut file = File::open(script)?;
-    let mut source = String::new();
-    file.read_to_string(&mut source)?;
-    run(source);
+/// Splits off everything after the script or the `-e` code, which belongs to
+/// the script even when it looks like an option, so that clap never sees it.
+/// A `--` just before it is dropped, as clap would.
+fn split_script_args(argv: Vec<OsString>) -> (Vec<OsString>, Vec<String>) {
+    let mut end = argv.len();
+    let mut i = 1;
+    while i < argv.len() {
+        let arg = argv[i].to_string_lossy();
+        match arg.as_ref() {
+            "fmt" | "check" | "test" if i == 1 => break,
+            "-e" | "--eval" => {
+                end = (i + 2).min(argv.len());
+                break;
+            }
+            "--emit" | "--output" => i += 1,
+            "--" => {
+                end = (i + 2).min(argv.len());
+                break;
+            }
+            "-" => {
+                end = i + 1;
+                break;
+            }
+            _ if arg.starts_with("--eval=")
+                || (arg.starts_with("-e") && !arg.starts_with("--")) =>
+            {
+                end = i + 1;
+                break;
+            }
+            _ if !arg.starts_with('-') => {
+                end = i + 1;
+                break;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    let mut rest: Vec<String> = argv[end..]
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    if rest.first().is_some_and(|arg| arg == "--") {
+        rest.remove(0);
+    }
+    (argv[..end].to_vec(), rest)
+}
+
+/// Why running some source failed, as the sysexits status to leave with.
+enum Failure {
+    Static,
+    Runtime,
+}
+
+impl Failure {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Static => 65,
+            Failure::Runtime => 70,
+        }
+    }
+}
+
+fn check(args: CheckArgs) -> std::io::Result<()> {
+    let mut scripts = Vec::new();
+    for path in args.paths {
+        if path.is_dir() {
+            find_scripts(&path, &mut scripts)?;
+        } else {
+            scripts.push(path);
+        }
+    }
+
+    let mut clean = true;
+    for script in scripts {
+        let source = match std::fs::read_to_string(&script) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("{}: {}", script.display(), error);
+                clean = false;
+                continue;
+            }
+        };
+        for error in lox_check(source) {
+            println!("{}:{}: {}", script.display(), error.line() + 1, error);
+            clean = false;
+        }
+    }
+    if !clean {
+        std::process::exit(1);
+    }
     Ok(())
 }
 
-fn run_prompt() -> std::io::Result<()> {
-    loop {
-        print!("> ");
-        stdout().flush()?;
-        let mut buffer = String::new();
-        stdin().read_line(&mut buffer)?;
-        if buffer.is_empty() {
-            break;
+fn test(args: TestArgs) -> std::io::Result<()> {
+    let mut scripts = Vec::new();
+    find_scripts(&args.directory, &mut scripts)?;
+    scripts.retain(|script| {
+        let path = script.to_string_lossy();
+        args.filter.is_empty() || args.filter.iter().any(|filter| path.contains(filter))
+    });
+
+    let (mut passed, mut failed) = (0, 0);
+    for (script, failures) in golden::test_all(&std::env::current_exe()?, scripts) {
+        if failures.is_empty() {
+            passed += 1;
+            continue;
+        }
+        failed += 1;
+        println!("FAIL {}", script.display());
+        for failure in failures {
+            for line in failure.lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Every .lox file under the directory, in a stable order.
+fn find_scripts(directory: &Path, scripts: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_scripts(&path, scripts)?;
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            scripts.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn fmt(args: FmtArgs) -> std::io::Result<()> {
+    let config = Config { width: args.width };
+    if args.files.is_empty() {
+        let mut source = String::new();
+        stdin().read_to_string(&mut source)?;
+        match format(source.clone(), &config) {
+            Ok(formatted) => print!("{}", formatted),
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("<stdin>:{}: {}", error.line(&source) + 1, error);
+                }
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let mut clean = true;
+    for path in args.files {
+        let source = std::fs::read_to_string(&path)?;
+        let formatted = match format(source.clone(), &config) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}:{}: {}", path.display(), error.line(&source) + 1, error);
+                }
+                clean = false;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if args.check {
+            println!("{}", path.display());
+            clean = false;
+        } else {
+            std::fs::write(&path, formatted)?;
+        }
+    }
+    if !clean {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// An interpreter printing to `output`, or to stdout if there's none.
+fn interpreter(args: Vec<String>, output: Option<&Path>) -> std::io::Result<Interpreter> {
+    let mut interpreter = match output {
+        Some(path) => {
+            Interpreter::with_output(BufWriter::new(File::create(path)?), std::io::stderr())
         }
-        run(buffer);
+        None => Interpreter::new(),
+    };
+    define_args(&mut interpreter, args);
+    Ok(interpreter)
+}
+
+fn define_args(interpreter: &mut Interpreter, args: Vec<String>) {
+    let args = args.into_iter().map(Value::Str).collect();
+    interpreter.set_global("args", Value::List(args));
+}
+
+fn run_file(
+    interpreter: Interpreter,
+    script: PathBuf,
+    emit: Option<Emit>,
+    optimized: bool,
+) -> std::io::Result<()> {
+    let mut source = String::new();
+    if script == Path::new("-") {
+        stdin().read_to_string(&mut source)?;
+    } else {
+        File::open(script)?.read_to_string(&mut source)?;
     }
+    run_script(interpreter, source, emit, optimized);
     Ok(())
 }
 
-fn run(source: String) {
-    let tokens = scan_tokens(source);
-    if let Ok(ref token) = tokens {
-        println!("{:?}", token);
+fn run_script(mut interpreter: Interpreter, source: String, emit: Option<Emit>, optimized: bool) {
+    let mut result = run(&mut interpreter, source, emit, optimized, false);
+    // exiting skips the flush dropping the output would do
+    if let (Ok(()), Err(error)) = (&result, interpreter.output().flush()) {
+        let error = interpret::Error::Output { kind: error.kind() };
+        result = Err(runtime_error(&mut interpreter, error));
+    }
+    if let Err(failure) = result {
+        std::process::exit(failure.exit_code());
+    }
+}
+
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".tree_walk_history"))
+}
+
+fn run_prompt(
+    mut interpreter: Interpreter,
+    emit: Option<Emit>,
+    optimized: bool,
+) -> std::io::Result<()> {
+    let mut editor = Editor::<Completion>::new().map_err(std::io::Error::other)?;
+    editor.set_helper(Some(Completion::default()));
+    let history = history_file();
+    if let Some(history) = &history {
+        // there's no history the first time
+        let _ = editor.load_history(history);
     }
-    match tokens {
-        Ok(tokens) => {
-            let expressions = parse(VecDeque::from_iter(tokens));
-            match expressions {
-                Ok(expressions) => {
-                    for expression in expressions {
-                        println!("{:?}", expression);
+
+    let mut buffer = String::new();
+    loop {
+        if let Some(completion) = editor.helper_mut() {
+            completion.globals = interpreter
+                .environment()
+                .globals()
+                .into_iter()
+                .map(|(name, _)| name.to_string())
+                .collect();
+        }
+        let prompt = if buffer.is_empty() { "> " } else { ". " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                if buffer.is_empty() {
+                    if let Some(command) = parse_command(&line) {
+                        editor.add_history_entry(line.trim());
+                        match command {
+                            Ok(command) => meta_command(&mut interpreter, command, emit, optimized),
+                            Err(error) => report(&mut interpreter, format_args!("{}", error)),
+                        }
+                        interpreter.output().flush()?;
+                        continue;
                     }
                 }
-                Err(parse_error) => {
-                    println!("error parsing: {:?}", parse_error);
+                buffer.push_str(&line);
+                buffer.push('\n');
+                if !is_complete(&buffer) {
+                    continue;
                 }
+                let source = std::mem::take(&mut buffer);
+                editor.add_history_entry(source.trim_end());
+                let _ = run(&mut interpreter, source, emit, optimized, true);
+                interpreter.output().flush()?;
+            }
+            Err(ReadlineError::Interrupted) => buffer.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(std::io::Error::other(error)),
+        }
+    }
+
+    if let Some(history) = &history {
+        editor
+            .save_history(history)
+            .map_err(std::io::Error::other)?;
+    }
+    Ok(())
+}
+
+fn meta_command(
+    interpreter: &mut Interpreter,
+    command: ReplCommand,
+    emit: Option<Emit>,
+    optimized: bool,
+) {
+    match command {
+        ReplCommand::Tokens(source) => {
+            let _ = run(interpreter, source, Some(Emit::Tokens), false, true);
+        }
+        ReplCommand::Ast(source) => {
+            let _ = run(interpreter, source, Some(Emit::AstSexpr), optimized, true);
+        }
+        ReplCommand::Env => {
+            let globals: Vec<String> = interpreter
+                .environment()
+                .globals()
+                .into_iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect();
+            for global in globals {
+                print(interpreter, format_args!("{}", global));
+            }
+        }
+        ReplCommand::Load(path) => match std::fs::read_to_string(&path) {
+            Ok(source) => {
+                let _ = run(interpreter, source, emit, optimized, false);
             }
+            Err(error) => report(interpreter, format_args!("{}: {}", path.display(), error)),
+        },
+        ReplCommand::Reset => {
+            interpreter.reset();
+            define_args(interpreter, vec![]);
+        }
+        ReplCommand::Time(source) => {
+            let start = Instant::now();
+            let _ = run(interpreter, source, emit, optimized, true);
+            print(interpreter, format_args!("took {:?}", start.elapsed()));
+        }
+        ReplCommand::Help => print(interpreter, format_args!("{}", HELP)),
+    }
+}
+
+/// At the prompt a bare expression at the end is printed rather than needing
+/// a semicolon.
+fn run(
+    interpreter: &mut Interpreter,
+    source: String,
+    emit: Option<Emit>,
+    optimized: bool,
+    prompt: bool,
+) -> Result<(), Failure> {
+    // a script's static errors are all reported at once, with their lines
+    if !prompt && !matches!(emit, Some(Emit::Tokens)) {
+        let errors = lox_check(source.clone());
+        for error in &errors {
+            report(
+                interpreter,
+                format_args!("[line {}] Error: {}", error.line() + 1, error),
+            );
+        }
+        if !errors.is_empty() {
+            return Err(Failure::Static);
         }
+    }
+    let tokens = match scan_tokens(source) {
+        Ok(tokens) => tokens,
         Err(scan_error) => {
-            println!("error scanning tokens: {:?}", scan_error)
+            let line = scan_error.line() + 1;
+            report(
+                interpreter,
+                format_args!("[line {}] Error: {}", line, scan_error),
+            );
+            return Err(Failure::Static);
+        }
+    };
+    if let Some(Emit::Tokens) = emit {
+        return write!(interpreter.output(), "{}", emit::tokens(&tokens)).map_err(|error| {
+            runtime_error(interpreter, interpret::Error::Output { kind: error.kind() })
+        });
+    }
+    let parsed = if prompt {
+        parse_repl(VecDeque::from_iter(tokens))
+            .map(|(mut statements, trailing)| {
+                let print_last = trailing.is_some();
+                statements.extend(trailing.map(|expression| Statement::Expression { expression }));
+                (statements, print_last)
+            })
+            .map_err(|(error, _)| error)
+    } else {
+        parse_program(VecDeque::from_iter(tokens)).map(|statements| (statements, false))
+    };
+    let (mut statements, print_last) = match parsed {
+        Ok(parsed) => parsed,
+        Err(parse_error) => {
+            report(interpreter, format_args!("Error: {}", parse_error));
+            return Err(Failure::Static);
+        }
+    };
+    if optimized {
+        let (optimized, folded) = optimize(statements);
+        report(interpreter, format_args!("folded {} nodes", folded));
+        statements = optimized;
+    }
+    let emitted = match emit {
+        Some(Emit::AstSexpr) => emit::sexpr(&statements),
+        Some(Emit::AstJson) => emit::json(&statements),
+        Some(Emit::AstDot) => emit::dot(&statements),
+        Some(Emit::Tokens) => String::new(),
+        None => {
+            let errors = resolve(&statements);
+            for error in &errors {
+                report(
+                    interpreter,
+                    format_args!("[line {}] Error: {}", error.line() + 1, error),
+                );
+            }
+            if !errors.is_empty() {
+                return Err(Failure::Static);
+            }
+            return execute(interpreter, &statements, print_last)
+                .map_err(|error| runtime_error(interpreter, error));
         }
+    };
+    write!(interpreter.output(), "{}", emitted).map_err(|error| {
+        runtime_error(interpreter, interpret::Error::Output { kind: error.kind() })
+    })
+}
+
+/// Writes to wherever the interpreter's output goes, as `print` does.
+fn print(interpreter: &mut Interpreter, message: std::fmt::Arguments) {
+    if let Err(error) = writeln!(interpreter.output(), "{}", message) {
+        runtime_error(interpreter, interpret::Error::Output { kind: error.kind() });
+    }
+}
+
+/// Writes a line to the interpreter's diagnostics. There's nowhere left to
+/// report it if that fails too.
+fn report(interpreter: &mut Interpreter, message: std::fmt::Arguments) {
+    let _ = writeln!(interpreter.diagnostics(), "{}", message);
+}
+
+fn runtime_error(interpreter: &mut Interpreter, error: interpret::Error) -> Failure {
+    match error.line() {
+        Some(line) => report(interpreter, format_args!("{}\n[line {}]", error, line + 1)),
+        None => report(interpreter, format_args!("{}", error)),
     }
+    Failure::Runtime
+}
+
+fn execute(
+    interpreter: &mut Interpreter,
+    statements: &[Statement],
+    print_last: bool,
+) -> Result<(), interpret::Error> {
+    let value = interpreter.run(statements)?;
+    if print_last {
+        writeln!(interpreter.output(), "{}", value)
+            .map_err(|error| interpret::Error::Output { kind: error.kind() })?;
+    }
+    Ok(())
 }
@@ -0,0 +1,300 @@
+//! Functions written in Rust that Lox can call, and the conversions between
+//! Lox values and Rust types that their arguments and results go through.
+
+use crate::expression::Value;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+type Function = dyn Fn(&[Value]) -> Result<Value, String>;
+
+/// A Rust function bound to a global. It gets exactly `arity` arguments, and
+/// an error it returns is reported as a runtime error at the call.
+pub struct Native {
+    name: String,
+    arity: usize,
+    function: Box<Function>,
+}
+
+impl Native {
+    pub fn new(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn call(&self, arguments: &[Value]) -> Result<Value, String> {
+        (self.function)(arguments)
+    }
+}
+
+impl std::fmt::Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+// a function is only ever equal to itself
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Seconds since the first interpreter was made. The book counts from the
+/// epoch, but a Lox number is an f32, which can't tell seconds apart that far
+/// out.
+pub fn clock() -> Native {
+    let start = *START.get_or_init(Instant::now);
+    Native::new("clock", 0, move |_| {
+        Ok(start.elapsed().as_secs_f64().into_lox())
+    })
+}
+
+/// A Rust type that can be taken out of a Lox value.
+pub trait FromLox: Sized {
+    fn from_lox(value: &Value) -> Result<Self, String>;
+}
+
+/// A Rust type that can be handed to Lox.
+pub trait IntoLox {
+    fn into_lox(self) -> Value;
+}
+
+fn mismatch<T>(expected: &str, value: &Value) -> Result<T, String> {
+    Err(format!("Expected {} but got '{}'.", expected, value))
+}
+
+impl FromLox for Value {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoLox for Value {
+    fn into_lox(self) -> Value {
+        self
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Float(n) => Ok(*n as f64),
+            Value::Int(n) => Ok(*n as f64),
+            _ => mismatch("a number", value),
+        }
+    }
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self) -> Value {
+        Value::Float(self as f32)
+    }
+}
+
+impl FromLox for i64 {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            // `as` would saturate, so a number past i64's range isn't one
+            Value::Float(n)
+                if n.fract() == 0.0 && *n >= i64::MIN as f32 && *n < i64::MAX as f32 =>
+            {
+                Ok(*n as i64)
+            }
+            Value::Int(n) => Ok(*n),
+            _ => mismatch("an integer", value),
+        }
+    }
+}
+
+// Lox arithmetic is all floating point, so integers go in as numbers
+impl IntoLox for i64 {
+    fn into_lox(self) -> Value {
+        Value::Float(self as f32)
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::True => Ok(true),
+            Value::False => Ok(false),
+            _ => mismatch("a boolean", value),
+        }
+    }
+}
+
+impl IntoLox for bool {
+    fn into_lox(self) -> Value {
+        if self {
+            Value::True
+        } else {
+            Value::False
+        }
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Str(s) => Ok(s.clone()),
+            _ => mismatch("a string", value),
+        }
+    }
+}
+
+impl IntoLox for String {
+    fn into_lox(self) -> Value {
+        Value::Str(self)
+    }
+}
+
+impl IntoLox for &str {
+    fn into_lox(self) -> Value {
+        Value::Str(self.to_string())
+    }
+}
+
+impl IntoLox for () {
+    fn into_lox(self) -> Value {
+        Value::Nil
+    }
+}
+
+/// nil is None.
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Nil => Ok(None),
+            _ => T::from_lox(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self) -> Value {
+        self.map_or(Value::Nil, IntoLox::into_lox)
+    }
+}
+
+impl<T: FromLox> FromLox for Vec<T> {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::List(values) => values.iter().map(T::from_lox).collect(),
+            _ => mismatch("a list", value),
+        }
+    }
+}
+
+impl<T: IntoLox> IntoLox for Vec<T> {
+    fn into_lox(self) -> Value {
+        Value::List(self.into_iter().map(IntoLox::into_lox).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(Ok(1.5), f64::from_lox(&Value::Float(1.5)));
+        assert_eq!(Ok(2.0), f64::from_lox(&Value::Int(2)));
+        assert_eq!(Ok(3), i64::from_lox(&Value::Float(3.0)));
+        assert_eq!(
+            Err("Expected an integer but got '3.5'.".to_string()),
+            i64::from_lox(&Value::Float(3.5))
+        );
+        assert!(i64::from_lox(&Value::Float(1e30)).is_err());
+        assert!(i64::from_lox(&Value::Float(-1e30)).is_err());
+        assert!(i64::from_lox(&Value::Float(f32::INFINITY)).is_err());
+        assert_eq!(Ok(i64::MIN), i64::from_lox(&Value::Float(i64::MIN as f32)));
+        assert_eq!(
+            Err("Expected a number but got 'nil'.".to_string()),
+            f64::from_lox(&Value::Nil)
+        );
+        assert_eq!(Value::Float(4.0), 4i64.into_lox());
+        assert_eq!(Value::Float(0.25), 0.25f64.into_lox());
+    }
+
+    #[test]
+    fn test_bools_and_strings() {
+        assert_eq!(Ok(true), bool::from_lox(&Value::True));
+        // only true and false convert, not whatever is truthy
+        assert!(bool::from_lox(&Value::Nil).is_err());
+        assert_eq!(Value::False, false.into_lox());
+        assert_eq!(
+            Ok("a".to_string()),
+            String::from_lox(&Value::Str("a".to_string()))
+        );
+        assert_eq!(
+            Err("Expected a string but got '1'.".to_string()),
+            String::from_lox(&Value::Float(1.0))
+        );
+        assert_eq!(Value::Str("b".to_string()), "b".into_lox());
+        assert_eq!(Value::Nil, ().into_lox());
+    }
+
+    #[test]
+    fn test_options_and_lists() {
+        assert_eq!(Ok(None), Option::<f64>::from_lox(&Value::Nil));
+        assert_eq!(Ok(Some(1.0)), Option::<f64>::from_lox(&Value::Float(1.0)));
+        assert_eq!(Value::Nil, None::<bool>.into_lox());
+        assert_eq!(Value::True, Some(true).into_lox());
+
+        let list = Value::List(vec![Value::Str("a".to_string()), Value::Nil]);
+        assert_eq!(
+            Ok(vec![Some("a".to_string()), None]),
+            Vec::<Option<String>>::from_lox(&list)
+        );
+        assert_eq!(
+            Err("Expected a string but got 'nil'.".to_string()),
+            Vec::<String>::from_lox(&list)
+        );
+        assert_eq!(
+            Value::List(vec![Value::Float(1.0), Value::Float(2.0)]),
+            vec![1i64, 2].into_lox()
+        );
+    }
+
+    #[test]
+    fn test_native() {
+        let add = Native::new("add", 2, |arguments| {
+            Ok((f64::from_lox(&arguments[0])? + f64::from_lox(&arguments[1])?).into_lox())
+        });
+        assert_eq!("add", add.name());
+        assert_eq!(2, add.arity());
+        assert_eq!(
+            Ok(Value::Float(3.0)),
+            add.call(&[Value::Float(1.0), Value::Float(2.0)])
+        );
+        assert!(add.call(&[Value::Nil, Value::Float(2.0)]).is_err());
+        assert_eq!("<native fn add>", format!("{:?}", add));
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = clock();
+        assert_eq!(0, clock.arity());
+        match clock.call(&[]) {
+            Ok(Value::Float(seconds)) => assert!(seconds >= 0.0),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
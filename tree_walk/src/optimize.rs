@@ -0,0 +1,408 @@
+use crate::expression::{binding_power, precedence, Expression, Value, ASSIGNMENT, PRIMARY, UNARY};
+use crate::interpret::{binary, unary};
+use crate::scan::{Token, TokenType};
+use crate::statement::Statement;
+
+/// Folds constant subtrees, drops groupings the tree doesn't need and applies
+/// identities that can't change the result. Anything that would raise a
+/// runtime error is left alone, so the error and its line still happen when
+/// the program runs. Returns the new statements and how many nodes were folded.
+pub fn optimize(statements: Vec<Statement>) -> (Vec<Statement>, usize) {
+    let mut optimizer = Optimizer { folded: 0 };
+    let statements = statements
+        .into_iter()
+        .map(|statement| optimizer.statement(statement))
+        .collect();
+    (statements, optimizer.folded)
+}
+
+pub fn fold(expression: Expression) -> (Box<Expression>, usize) {
+    let mut optimizer = Optimizer { folded: 0 };
+    let expression = optimizer.expression(expression, 0);
+    (expression, optimizer.folded)
+}
+
+struct Optimizer {
+    folded: usize,
+}
+
+impl Optimizer {
+    fn statement(&mut self, statement: Statement) -> Statement {
+        match statement {
+            Statement::Expression { expression } => Statement::Expression {
+                expression: self.expression(*expression, 0),
+            },
+            Statement::Print { expression } => Statement::Print {
+                expression: self.expression(*expression, 0),
+            },
+            Statement::Var { name, initializer } => Statement::Var {
+                name,
+                initializer: initializer.map(|expression| self.expression(*expression, 0)),
+            },
+            Statement::Block { statements } => Statement::Block {
+                statements: statements
+                    .into_iter()
+                    .map(|statement| self.statement(statement))
+                    .collect(),
+            },
+        }
+    }
+
+    // `minimum` is the precedence the surrounding expression needs here, the
+    // same as for expression::print_infix.
+    fn expression(&mut self, expression: Expression, minimum: u8) -> Box<Expression> {
+        let expression = match expression {
+            Expression::Grouping { expression } => {
+                let expression = self.expression(*expression, 0);
+                if precedence(&expression) >= minimum {
+                    self.folded += 1;
+                    return expression;
+                }
+                Expression::Grouping { expression }
+            }
+            Expression::Unary {
+                operator,
+                expression,
+            } => {
+                let expression = self.expression(*expression, UNARY);
+                // -n is already as folded as a negative number gets
+                let negative_number = operator.token_type() == TokenType::Minus
+                    && matches!(
+                        *expression,
+                        Expression::Literal {
+                            value: Value::Float(_),
+                            ..
+                        }
+                    );
+                if let (false, Some(value)) = (negative_number, constant(&expression)) {
+                    if let Ok(value) = unary(&operator, &value) {
+                        if let Some(folded) = self.literal(value, &operator, minimum) {
+                            return folded;
+                        }
+                    }
+                }
+                match (operator.token_type(), *expression) {
+                    // --n is n and !!b is b, as long as they were already a
+                    // number and a bool
+                    (
+                        TokenType::Minus,
+                        Expression::Unary {
+                            operator: inner,
+                            expression,
+                        },
+                    ) if inner.token_type() == TokenType::Minus && is_number(&expression) => {
+                        self.folded += 1;
+                        return self.fits(*expression, minimum);
+                    }
+                    (
+                        TokenType::Bang,
+                        Expression::Unary {
+                            operator: inner,
+                            expression,
+                        },
+                    ) if inner.token_type() == TokenType::Bang && is_bool(&expression) => {
+                        self.folded += 1;
+                        return self.fits(*expression, minimum);
+                    }
+                    (_, expression) => Expression::Unary {
+                        operator,
+                        expression: Box::new(expression),
+                    },
+                }
+            }
+            Expression::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let power = binding_power(&operator);
+                let left = self.expression(*left, power);
+                let right = self.expression(*right, power + 1);
+                if let (Some(a), Some(b)) = (constant(&left), constant(&right)) {
+                    if let Ok(value) = binary(&a, &operator, &b) {
+                        if let Some(folded) = self.literal(value, &operator, minimum) {
+                            return folded;
+                        }
+                    }
+                }
+                // n + 0 is left alone since -0 + 0 is 0
+                let keep_left = match operator.token_type() {
+                    TokenType::Star | TokenType::Slash => {
+                        is_number(&left) && is_literal(&right, 1.0)
+                    }
+                    TokenType::Minus => is_number(&left) && is_literal(&right, 0.0),
+                    _ => false,
+                };
+                if keep_left {
+                    self.folded += 1;
+                    return self.fits(*left, minimum);
+                }
+                if operator.token_type() == TokenType::Star
+                    && is_literal(&left, 1.0)
+                    && is_number(&right)
+                {
+                    self.folded += 1;
+                    return self.fits(*right, minimum);
+                }
+                Expression::Binary {
+                    left,
+                    operator,
+                    right,
+                }
+            }
+            Expression::Assign { name, value } => Expression::Assign {
+                name,
+                value: self.expression(*value, ASSIGNMENT),
+            },
+            Expression::Call {
+                callee,
+                paren,
+                arguments,
+            } => Expression::Call {
+                callee: self.expression(*callee, PRIMARY),
+                paren,
+                arguments: arguments
+                    .into_iter()
+                    .map(|argument| *self.expression(argument, ASSIGNMENT))
+                    .collect(),
+            },
+            expression @ (Expression::Literal { .. } | Expression::Variable { .. }) => expression,
+        };
+        Box::new(expression)
+    }
+
+    // A folded value as the parser would have written it: a negative number
+    // is a minus applied to a literal. Infinities and NaN have no literal at
+    // all, so those folds are left undone.
+    fn literal(&mut self, value: Value, operator: &Token, minimum: u8) -> Option<Box<Expression>> {
+        let expression = match value {
+            Value::Float(n) if !n.is_finite() => return None,
+            Value::Float(n) if n.is_sign_negative() => Expression::Unary {
+                operator: Token::new(TokenType::Minus, operator.line()),
+                expression: Box::new(Expression::Literal {
+                    value: Value::Float(-n),
+                    line: operator.line(),
+                }),
+            },
+            value => Expression::Literal {
+                value,
+                line: operator.line(),
+            },
+        };
+        self.folded += 1;
+        Some(self.fits(expression, minimum))
+    }
+
+    // What an identity leaves behind may be a grouping that's now redundant,
+    // or something looser than its surroundings need.
+    fn fits(&mut self, expression: Expression, minimum: u8) -> Box<Expression> {
+        match expression {
+            Expression::Grouping { expression } if precedence(&expression) >= minimum => {
+                self.folded += 1;
+                expression
+            }
+            expression if precedence(&expression) < minimum => Box::new(Expression::Grouping {
+                expression: Box::new(expression),
+            }),
+            expression => Box::new(expression),
+        }
+    }
+}
+
+/// The value of a literal, or of a negative number.
+fn constant(expression: &Expression) -> Option<Value> {
+    match expression {
+        Expression::Literal { value, .. } => Some(value.clone()),
+        Expression::Unary {
+            operator,
+            expression,
+        } if operator.token_type() == TokenType::Minus => match &**expression {
+            Expression::Literal {
+                value: Value::Float(n),
+                ..
+            } => Some(Value::Float(-n)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_literal(expression: &Expression, n: f32) -> bool {
+    matches!(expression, Expression::Literal { value: Value::Float(m), .. } if *m == n)
+}
+
+/// Whether the expression can only evaluate to a number, if it doesn't fail.
+fn is_number(expression: &Expression) -> bool {
+    match expression {
+        Expression::Literal { value, .. } => matches!(value, Value::Float(_)),
+        Expression::Unary { operator, .. } => operator.token_type() == TokenType::Minus,
+        Expression::Binary { operator, .. } => matches!(
+            operator.token_type(),
+            TokenType::Minus | TokenType::Star | TokenType::Slash
+        ),
+        Expression::Grouping { expression } => is_number(expression),
+        Expression::Variable { .. } | Expression::Assign { .. } | Expression::Call { .. } => false,
+    }
+}
+
+/// Whether the expression can only evaluate to true or false, if it doesn't fail.
+fn is_bool(expression: &Expression) -> bool {
+    match expression {
+        Expression::Literal { value, .. } => matches!(value, Value::True | Value::False),
+        Expression::Unary { operator, .. } => operator.token_type() == TokenType::Bang,
+        Expression::Binary { operator, .. } => !matches!(
+            operator.token_type(),
+            TokenType::Plus | TokenType::Minus | TokenType::Star | TokenType::Slash
+        ),
+        Expression::Grouping { expression } => is_bool(expression),
+        Expression::Variable { .. } | Expression::Assign { .. } | Expression::Call { .. } => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::{print_as, Notation};
+    use crate::parse::{parse, parse_program};
+    use crate::scan::scan_tokens;
+    use std::collections::VecDeque;
+
+    fn folded(source: &str) -> (String, usize) {
+        let expression = parse(VecDeque::from(scan_tokens(source.to_string()).unwrap()))
+            .unwrap()
+            .remove(0);
+        let (expression, folded) = fold(*expression);
+        (print_as(&expression, Notation::Infix), folded)
+    }
+
+    #[test]
+    fn test_fold_literals() {
+        assert_eq!(("7".to_string(), 2), folded("1 + 2 * 3"));
+        assert_eq!(("\"ab\"".to_string(), 1), folded("\"a\" + \"b\""));
+        assert_eq!(("false".to_string(), 1), folded("!true"));
+        assert_eq!(("true".to_string(), 1), folded("!nil"));
+        assert_eq!(("-2".to_string(), 0), folded("-2"));
+        assert_eq!(("true".to_string(), 2), folded("1 < 2 == true"));
+        assert_eq!(("false".to_string(), 1), folded("\"1\" == 1"));
+    }
+
+    #[test]
+    fn test_fold_negative_numbers() {
+        // kept as a minus applied to a literal, as the parser makes them
+        assert_eq!(("-2".to_string(), 1), folded("1 - 3"));
+        assert_eq!(("-6".to_string(), 3), folded("2 * (1 - 4)"));
+        assert_eq!(("1".to_string(), 2), folded("-(-1)"));
+        assert_eq!(("1".to_string(), 1), folded("--1"));
+        assert_eq!(("1".to_string(), 3), folded("-(2 - 3)"));
+        assert_eq!(("false".to_string(), 1), folded("!-2"));
+        assert_eq!(("f(-1)".to_string(), 1), folded("f(0 - 1)"));
+        assert_eq!(("(-1)(a)".to_string(), 1), folded("(0 - 1)(a)"));
+
+        let expression = parse(VecDeque::from(scan_tokens("1 - 3".to_string()).unwrap()))
+            .unwrap()
+            .remove(0);
+        let (expression, _) = fold(*expression);
+        match *expression {
+            Expression::Unary {
+                operator,
+                expression,
+            } => {
+                assert_eq!(TokenType::Minus, operator.token_type());
+                assert_eq!(
+                    Expression::Literal {
+                        value: Value::Float(2.0),
+                        line: 0,
+                    },
+                    *expression
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_no_fold_non_finite() {
+        // infinities and NaN have no literal to fold into
+        assert_eq!(("1 / 0".to_string(), 0), folded("1 / 0"));
+        assert_eq!(("-1 / 0".to_string(), 0), folded("-1 / 0"));
+        assert_eq!(("0 / 0 + 1".to_string(), 0), folded("0 / 0 + 1"));
+        assert_eq!(("-(1 / 0)".to_string(), 0), folded("-(1 / 0)"));
+    }
+
+    #[test]
+    fn test_fold_groupings() {
+        assert_eq!(("9".to_string(), 5), folded("(1 + 2) * (4 - 1)"));
+        assert_eq!(("a * 2 + 1".to_string(), 1), folded("(a * 2) + 1"));
+        assert_eq!(("(a + 2) * 1".to_string(), 0), folded("(a + 2) * 1"));
+        assert_eq!(("1 - (a - 2)".to_string(), 0), folded("1 - (a - 2)"));
+        assert_eq!(("a".to_string(), 2), folded("((a))"));
+        assert_eq!(("-a".to_string(), 1), folded("-(a)"));
+    }
+
+    #[test]
+    fn test_no_fold_runtime_errors() {
+        assert_eq!(("-\"a\"".to_string(), 0), folded("-\"a\""));
+        assert_eq!(("3 + \"a\"".to_string(), 1), folded("1 + 2 + \"a\""));
+        assert_eq!(("true < 1".to_string(), 0), folded("true < 1"));
+        assert_eq!(("nil * 1".to_string(), 0), folded("nil * 1"));
+    }
+
+    #[test]
+    fn test_identities() {
+        assert_eq!(("-a".to_string(), 1), folded("-a * 1"));
+        assert_eq!(("a * b".to_string(), 2), folded("1 * (a * b)"));
+        assert_eq!(("a / b".to_string(), 1), folded("a / b / 1"));
+        assert_eq!(("-a".to_string(), 1), folded("-a - 0"));
+        assert_eq!(("a == b".to_string(), 2), folded("!!(a == b)"));
+        assert_eq!(("a * b".to_string(), 2), folded("--(a * b)"));
+        assert_eq!(("2 / (a * b)".to_string(), 2), folded("2 / (1 * (a * b))"));
+    }
+
+    #[test]
+    fn test_no_unsafe_identities() {
+        // a might not be a number, or a bool
+        assert_eq!(("a * 1".to_string(), 0), folded("a * 1"));
+        assert_eq!(("!!a".to_string(), 0), folded("!!a"));
+        assert_eq!(("--a".to_string(), 0), folded("--a"));
+        assert_eq!(("(a = 2) * 1".to_string(), 0), folded("(a = 2) * 1"));
+        // -0 + 0 is 0
+        assert_eq!(("-a + 0".to_string(), 0), folded("-a + 0"));
+    }
+
+    #[test]
+    fn test_keeps_error_lines() {
+        let tokens = scan_tokens("print 1 +\n  2 +\n  -\"a\";".to_string()).unwrap();
+        let statements = parse_program(VecDeque::from(tokens)).unwrap();
+        let (statements, folded) = optimize(statements);
+        assert_eq!(1, folded);
+        match &statements[..] {
+            [Statement::Print { expression }] => match &**expression {
+                Expression::Binary {
+                    operator, right, ..
+                } => {
+                    assert_eq!(1, operator.line());
+                    match &**right {
+                        Expression::Unary { operator, .. } => assert_eq!(2, operator.line()),
+                        other => panic!("unexpected {:?}", other),
+                    }
+                }
+                other => panic!("unexpected {:?}", other),
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_optimize_statements() {
+        let tokens =
+            scan_tokens("var a = 1 + 1; { a = (a) * 1; print !false; }".to_string()).unwrap();
+        let statements = parse_program(VecDeque::from(tokens)).unwrap();
+        let (statements, folded) = optimize(statements);
+        assert_eq!(3, folded);
+        let expected = parse_program(VecDeque::from(
+            scan_tokens("var a = 2; { a = a * 1; print true; }".to_string()).unwrap(),
+        ))
+        .unwrap();
+        assert_eq!(expected, statements);
+    }
+}
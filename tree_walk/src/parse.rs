@@ -1,14 +1,38 @@
 use crate::expression::Expression;
 use crate::expression::Value::*;
 use crate::scan::{Token, TokenType};
+use crate::statement::Statement;
 use std::collections::VecDeque;
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
     Placeholder,
     UnclosedParen,
+    UnclosedBrace,
     NoExpression,
     MissingEOF,
+    MissingSemicolon,
+    MissingVariableName,
+    InvalidAssignmentTarget,
+    UnclosedCall,
+    TooManyArguments,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Placeholder => write!(f, "Unexpected end of input."),
+            Error::UnclosedParen => write!(f, "Expect ')' after expression."),
+            Error::UnclosedBrace => write!(f, "Expect '}}' after block."),
+            Error::NoExpression => write!(f, "Expect expression."),
+            Error::MissingEOF => write!(f, "Expect end of input."),
+            Error::MissingSemicolon => write!(f, "Expect ';'."),
+            Error::MissingVariableName => write!(f, "Expect variable name."),
+            Error::InvalidAssignmentTarget => write!(f, "Invalid assignment target."),
+            Error::UnclosedCall => write!(f, "Expect ')' after arguments."),
+            Error::TooManyArguments => write!(f, "Can't have more than 255 arguments."),
+        }
+    }
 }
 
 pub fn parse(mut tokens: VecDeque<Token>) -> Result<Vec<Box<Expression>>, Error> {
@@ -18,22 +42,203 @@ pub fn parse(mut tokens: VecDeque<Token>) -> Result<Vec<Box<Expression>>, Error>
     }
     if tokens[0].token_type() != TokenType::EOF {
         Err(Error::MissingEOF)
-    } else if expressions.len() < 1 {
+    } else if expressions.is_empty() {
         Err(Error::NoExpression)
     } else {
         Ok(expressions)
     }
 }
 
+pub fn parse_program(mut tokens: VecDeque<Token>) -> Result<Vec<Statement>, Error> {
+    let mut statements = Vec::new();
+    while tokens.len() > 1 {
+        statements.push(declaration(&mut tokens)?);
+    }
+    if tokens.is_empty() || tokens[0].token_type() != TokenType::EOF {
+        Err(Error::MissingEOF)
+    } else {
+        Ok(statements)
+    }
+}
+
+/// A single expression and nothing after it. An error comes with the line of
+/// the token it was found at.
+pub fn parse_expression(mut tokens: VecDeque<Token>) -> Result<Box<Expression>, (Error, usize)> {
+    let line = tokens.front().map_or(0, Token::line);
+    let at = |tokens: &VecDeque<Token>, error| (error, tokens.front().map_or(line, Token::line));
+    let expression = expression(&mut tokens).map_err(|error| at(&tokens, error))?;
+    if next_is(&tokens, TokenType::EOF) {
+        Ok(expression)
+    } else {
+        Err(at(&tokens, Error::MissingEOF))
+    }
+}
+
+/// The statements typed at the prompt, and the expression ending them, if any.
+pub type Repl = (Vec<Statement>, Option<Box<Expression>>);
+
+/// Like parse_program, except the last statement may be an expression with
+/// no semicolon, as typed at the prompt. It comes back separately so its value
+/// can be printed. An error comes with the line of the token it was found at.
+pub fn parse_repl(mut tokens: VecDeque<Token>) -> Result<Repl, (Error, usize)> {
+    let line = tokens.front().map_or(0, Token::line);
+    repl(&mut tokens).map_err(|error| (error, tokens.front().map_or(line, Token::line)))
+}
+
+fn repl(tokens: &mut VecDeque<Token>) -> Result<Repl, Error> {
+    let mut statements = Vec::new();
+    while tokens.len() > 1 {
+        if next_is(tokens, TokenType::Var)
+            || next_is(tokens, TokenType::Print)
+            || next_is(tokens, TokenType::LeftBrace)
+        {
+            statements.push(declaration(tokens)?);
+            continue;
+        }
+        let expression = expression(tokens)?;
+        if next_is(tokens, TokenType::EOF) {
+            return Ok((statements, Some(expression)));
+        }
+        consume(tokens, TokenType::Semicolon, Error::MissingSemicolon)?;
+        statements.push(Statement::Expression { expression });
+    }
+    if tokens.is_empty() || tokens[0].token_type() != TokenType::EOF {
+        Err(Error::MissingEOF)
+    } else {
+        Ok((statements, None))
+    }
+}
+
+/// Like parse_program, but after an error skips ahead to the next statement
+/// and carries on, so that every error is found. Each error comes with the
+/// line of the token it was found at.
+pub fn parse_program_recovering(
+    mut tokens: VecDeque<Token>,
+) -> (Vec<Statement>, Vec<(Error, usize)>) {
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+    while tokens.len() > 1 {
+        let remaining = tokens.len();
+        let line = tokens[0].line();
+        match declaration(&mut tokens) {
+            Ok(statement) => statements.push(statement),
+            Err(error) => {
+                let line = tokens.front().map_or(line, Token::line);
+                errors.push((error, line));
+                synchronize(&mut tokens, remaining);
+            }
+        }
+    }
+    (statements, errors)
+}
+
+fn synchronize(tokens: &mut VecDeque<Token>, remaining: usize) {
+    if tokens.len() == remaining {
+        tokens.pop_front();
+    }
+    while tokens.len() > 1 {
+        match tokens[0].token_type() {
+            TokenType::Semicolon => {
+                tokens.pop_front();
+                return;
+            }
+            TokenType::Class
+            | TokenType::Fun
+            | TokenType::Var
+            | TokenType::For
+            | TokenType::If
+            | TokenType::While
+            | TokenType::Print
+            | TokenType::Return => return,
+            _ => {
+                tokens.pop_front();
+            }
+        }
+    }
+}
+
+fn next_is(tokens: &VecDeque<Token>, type_: TokenType) -> bool {
+    !tokens.is_empty() && tokens[0].token_type() == type_
+}
+
+fn consume(tokens: &mut VecDeque<Token>, type_: TokenType, error: Error) -> Result<Token, Error> {
+    if next_is(tokens, type_) {
+        tokens.pop_front().ok_or(Error::Placeholder)
+    } else {
+        Err(error)
+    }
+}
+
+fn declaration(tokens: &mut VecDeque<Token>) -> Result<Statement, Error> {
+    if next_is(tokens, TokenType::Var) {
+        return var_declaration(tokens);
+    }
+    statement(tokens)
+}
+
+fn var_declaration(tokens: &mut VecDeque<Token>) -> Result<Statement, Error> {
+    tokens.pop_front().ok_or(Error::Placeholder)?;
+    let name = match tokens.pop_front() {
+        Some(token) if matches!(token.token_type(), TokenType::Identifier(_)) => token,
+        _ => return Err(Error::MissingVariableName),
+    };
+    let mut initializer = None;
+    if next_is(tokens, TokenType::Equal) {
+        tokens.pop_front().ok_or(Error::Placeholder)?;
+        initializer = Some(expression(tokens)?);
+    }
+    consume(tokens, TokenType::Semicolon, Error::MissingSemicolon)?;
+    Ok(Statement::Var { name, initializer })
+}
+
+fn statement(tokens: &mut VecDeque<Token>) -> Result<Statement, Error> {
+    if next_is(tokens, TokenType::Print) {
+        tokens.pop_front().ok_or(Error::Placeholder)?;
+        let expression = expression(tokens)?;
+        consume(tokens, TokenType::Semicolon, Error::MissingSemicolon)?;
+        return Ok(Statement::Print { expression });
+    }
+    if next_is(tokens, TokenType::LeftBrace) {
+        return block(tokens);
+    }
+    let expression = expression(tokens)?;
+    consume(tokens, TokenType::Semicolon, Error::MissingSemicolon)?;
+    Ok(Statement::Expression { expression })
+}
+
+fn block(tokens: &mut VecDeque<Token>) -> Result<Statement, Error> {
+    tokens.pop_front().ok_or(Error::Placeholder)?;
+    let mut statements = Vec::new();
+    while !next_is(tokens, TokenType::RightBrace) && !next_is(tokens, TokenType::EOF) {
+        statements.push(declaration(tokens)?);
+    }
+    consume(tokens, TokenType::RightBrace, Error::UnclosedBrace)?;
+    Ok(Statement::Block { statements })
+}
+
 fn expression(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
-    equality(tokens)
+    assignment(tokens)
+}
+
+fn assignment(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
+    let expr = equality(tokens)?;
+
+    if next_is(tokens, TokenType::Equal) {
+        tokens.pop_front().ok_or(Error::Placeholder)?;
+        let value = assignment(tokens)?;
+        return match *expr {
+            Expression::Variable { name } => Ok(Box::new(Expression::Assign { name, value })),
+            _ => Err(Error::InvalidAssignmentTarget),
+        };
+    }
+    Ok(expr)
 }
 
 fn equality(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
     let mut expr = comparison(tokens)?;
 
     let operators = [TokenType::EqualEqual, TokenType::BangEqual];
-    while tokens.len() > 0 && operators.contains(&tokens[0].token_type()) {
+    while !tokens.is_empty() && operators.contains(&tokens[0].token_type()) {
         let operator = tokens.pop_front().ok_or(Error::Placeholder)?;
         let right = comparison(tokens)?;
         expr = Box::new(Expression::Binary {
@@ -54,7 +259,7 @@ fn comparison(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
         TokenType::Less,
         TokenType::LessEqual,
     ];
-    while tokens.len() > 0 && operators.contains(&tokens[0].token_type()) {
+    while !tokens.is_empty() && operators.contains(&tokens[0].token_type()) {
         let operator = tokens.pop_front().ok_or(Error::Placeholder)?;
         let right = term(tokens)?;
         expr = Box::new(Expression::Binary {
@@ -70,7 +275,7 @@ fn term(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
     let mut expr = factor(tokens)?;
 
     let operators = [TokenType::Minus, TokenType::Plus];
-    while tokens.len() > 0 && operators.contains(&tokens[0].token_type()) {
+    while !tokens.is_empty() && operators.contains(&tokens[0].token_type()) {
         let operator = tokens.pop_front().ok_or(Error::Placeholder)?;
         let right = factor(tokens)?;
         expr = Box::new(Expression::Binary {
@@ -86,7 +291,7 @@ fn factor(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
     let mut expr = unary(tokens)?;
 
     let operators = [TokenType::Slash, TokenType::Star];
-    while tokens.len() > 0 && operators.contains(&tokens[0].token_type()) {
+    while !tokens.is_empty() && operators.contains(&tokens[0].token_type()) {
         let operator = tokens.pop_front().ok_or(Error::Placeholder)?;
         let right = unary(tokens)?;
         expr = Box::new(Expression::Binary {
@@ -101,42 +306,75 @@ fn factor(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
 fn unary(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
     let operators = [TokenType::Minus, TokenType::Bang];
 
-    if tokens.len() > 0 && operators.contains(&tokens[0].token_type()) {
+    if !tokens.is_empty() && operators.contains(&tokens[0].token_type()) {
         let operator = tokens.pop_front().ok_or(Error::Placeholder)?;
-        let inner = primary(tokens)?;
+        let inner = unary(tokens)?;
         return Ok(Box::new(Expression::Unary {
             operator,
             expression: inner,
         }));
     }
-    primary(tokens)
+    call(tokens)
 }
 
-fn primary(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
-    if tokens.len() < 1 {
-        return Err(Error::Placeholder);
+/// The most arguments a call can pass.
+pub const MAX_ARGUMENTS: usize = 255;
+
+fn call(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
+    let mut callee = primary(tokens)?;
+    while next_is(tokens, TokenType::LeftParen) {
+        tokens.pop_front();
+        let mut arguments = Vec::new();
+        if !next_is(tokens, TokenType::RightParen) {
+            loop {
+                if arguments.len() == MAX_ARGUMENTS {
+                    return Err(Error::TooManyArguments);
+                }
+                arguments.push(*expression(tokens)?);
+                if !next_is(tokens, TokenType::Comma) {
+                    break;
+                }
+                tokens.pop_front();
+            }
+        }
+        let paren = consume(tokens, TokenType::RightParen, Error::UnclosedCall)?;
+        callee = Box::new(Expression::Call {
+            callee,
+            paren,
+            arguments,
+        });
     }
-    match tokens.pop_front().ok_or(Error::Placeholder)?.token_type() {
+    Ok(callee)
+}
+
+fn primary(tokens: &mut VecDeque<Token>) -> Result<Box<Expression>, Error> {
+    let value = match tokens.front().ok_or(Error::Placeholder)?.token_type() {
         TokenType::LeftParen => {
+            tokens.pop_front();
             let expression = expression(tokens)?;
-            let closing_paren = tokens.pop_front().ok_or(Error::UnclosedParen)?;
-            if closing_paren.token_type() != TokenType::RightParen {
-                return Err(Error::UnclosedParen);
-            }
-            Ok(Box::new(Expression::Grouping { expression }))
+            consume(tokens, TokenType::RightParen, Error::UnclosedParen)?;
+            return Ok(Box::new(Expression::Grouping { expression }));
         }
-        TokenType::Number(n) => Ok(Box::new(Expression::Literal { value: Float(n) })),
-        TokenType::Str(s) => Ok(Box::new(Expression::Literal { value: Str(s) })),
-        TokenType::True => Ok(Box::new(Expression::Literal { value: True })),
-        TokenType::False => Ok(Box::new(Expression::Literal { value: False })),
-        _ => Err(Error::NoExpression),
-    }
+        TokenType::Identifier(_) => {
+            let name = tokens.pop_front().ok_or(Error::Placeholder)?;
+            return Ok(Box::new(Expression::Variable { name }));
+        }
+        TokenType::Number(n) => Float(n),
+        TokenType::Str(s) => Str(s),
+        TokenType::True => True,
+        TokenType::False => False,
+        TokenType::Nil => Nil,
+        // left for the caller, so the error is reported where it was found
+        _ => return Err(Error::NoExpression),
+    };
+    let line = tokens.pop_front().ok_or(Error::Placeholder)?.line();
+    Ok(Box::new(Expression::Literal { value, line }))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::scan::{Token, TokenType};
+    use crate::scan::{scan_tokens, Token, TokenType};
 
     #[test]
     fn test_parse_no_expression() {
@@ -150,7 +388,10 @@ mod tests {
         let mut tokens = VecDeque::new();
         tokens.push_back(Token::new(TokenType::Number(5.0), 0));
         tokens.push_back(Token::new(TokenType::EOF, 0));
-        let expected = Box::new(Expression::Literal { value: Float(5.0) });
+        let expected = Box::new(Expression::Literal {
+            value: Float(5.0),
+            line: 0,
+        });
         assert_eq!(Ok(vec![expected]), parse(tokens));
 
         tokens = VecDeque::new();
@@ -158,19 +399,26 @@ mod tests {
         tokens.push_back(Token::new(TokenType::EOF, 0));
         let expected = Box::new(Expression::Literal {
             value: Str("foo".to_string()),
+            line: 0,
         });
         assert_eq!(Ok(vec![expected]), parse(tokens));
 
         tokens = VecDeque::new();
         tokens.push_back(Token::new(TokenType::False, 0));
         tokens.push_back(Token::new(TokenType::EOF, 0));
-        let expected = Box::new(Expression::Literal { value: False });
+        let expected = Box::new(Expression::Literal {
+            value: False,
+            line: 0,
+        });
         assert_eq!(Ok(vec![expected]), parse(tokens));
 
         tokens = VecDeque::new();
         tokens.push_back(Token::new(TokenType::True, 0));
         tokens.push_back(Token::new(TokenType::EOF, 0));
-        let expected = Box::new(Expression::Literal { value: True });
+        let expected = Box::new(Expression::Literal {
+            value: True,
+            line: 0,
+        });
         assert_eq!(Ok(vec![expected]), parse(tokens));
     }
 
@@ -182,7 +430,10 @@ mod tests {
         tokens.push_back(Token::new(TokenType::EOF, 0));
         let expected = Box::new(Expression::Unary {
             operator: Token::new(TokenType::Minus, 0),
-            expression: Box::new(Expression::Literal { value: Float(5.0) }),
+            expression: Box::new(Expression::Literal {
+                value: Float(5.0),
+                line: 0,
+            }),
         });
         assert_eq!(Ok(vec![expected]), parse(tokens));
 
@@ -192,7 +443,27 @@ mod tests {
         tokens.push_back(Token::new(TokenType::EOF, 0));
         let expected = Box::new(Expression::Unary {
             operator: Token::new(TokenType::Bang, 0),
-            expression: Box::new(Expression::Literal { value: True }),
+            expression: Box::new(Expression::Literal {
+                value: True,
+                line: 0,
+            }),
+        });
+        assert_eq!(Ok(vec![expected]), parse(tokens));
+
+        tokens = VecDeque::new();
+        tokens.push_back(Token::new(TokenType::Minus, 0));
+        tokens.push_back(Token::new(TokenType::Minus, 0));
+        tokens.push_back(Token::new(TokenType::Number(5.0), 0));
+        tokens.push_back(Token::new(TokenType::EOF, 0));
+        let expected = Box::new(Expression::Unary {
+            operator: Token::new(TokenType::Minus, 0),
+            expression: Box::new(Expression::Unary {
+                operator: Token::new(TokenType::Minus, 0),
+                expression: Box::new(Expression::Literal {
+                    value: Float(5.0),
+                    line: 0,
+                }),
+            }),
         });
         assert_eq!(Ok(vec![expected]), parse(tokens));
     }
@@ -204,9 +475,15 @@ mod tests {
         tokens.push_back(Token::new(TokenType::Number(6.0), 0));
         tokens.push_back(Token::new(TokenType::EOF, 0));
         let expected = Box::new(Expression::Binary {
-            left: Box::new(Expression::Literal { value: Float(5.0) }),
+            left: Box::new(Expression::Literal {
+                value: Float(5.0),
+                line: 0,
+            }),
             operator: Token::new(type_, 0),
-            right: Box::new(Expression::Literal { value: Float(6.0) }),
+            right: Box::new(Expression::Literal {
+                value: Float(6.0),
+                line: 0,
+            }),
         });
         assert_eq!(Ok(vec![expected]), parse(tokens));
     }
@@ -245,7 +522,10 @@ mod tests {
         tokens.push_back(Token::new(TokenType::RightParen, 0));
         tokens.push_back(Token::new(TokenType::EOF, 0));
         let expected = Box::new(Expression::Grouping {
-            expression: Box::new(Expression::Literal { value: Float(5.0) }),
+            expression: Box::new(Expression::Literal {
+                value: Float(5.0),
+                line: 0,
+            }),
         });
         assert_eq!(Ok(vec![expected]), parse(tokens));
     }
@@ -261,9 +541,15 @@ mod tests {
         tokens.push_back(Token::new(TokenType::EOF, 0));
         let expected = Box::new(Expression::Grouping {
             expression: Box::new(Expression::Binary {
-                left: Box::new(Expression::Literal { value: Float(5.0) }),
+                left: Box::new(Expression::Literal {
+                    value: Float(5.0),
+                    line: 0,
+                }),
                 operator: Token::new(TokenType::Star, 0),
-                right: Box::new(Expression::Literal { value: Float(6.0) }),
+                right: Box::new(Expression::Literal {
+                    value: Float(6.0),
+                    line: 0,
+                }),
             }),
         });
         assert_eq!(Ok(vec![expected]), parse(tokens));
@@ -287,4 +573,243 @@ mod tests {
         tokens.push_back(Token::new(TokenType::EOF, 0));
         assert_eq!(Err(Error::UnclosedParen), parse(tokens));
     }
+
+    fn program(source: &str) -> Result<Vec<Statement>, Error> {
+        parse_program(VecDeque::from(scan_tokens(source.to_string()).unwrap()))
+    }
+
+    fn identifier(name: &str, line: usize) -> Token {
+        Token::new(TokenType::Identifier(name.to_string()), line)
+    }
+
+    #[test]
+    fn test_empty_program() {
+        assert_eq!(Ok(vec![]), program(""));
+    }
+
+    #[test]
+    fn test_print_statement() {
+        let expected = Statement::Print {
+            expression: Box::new(Expression::Literal {
+                value: Nil,
+                line: 0,
+            }),
+        };
+        assert_eq!(Ok(vec![expected]), program("print nil;"));
+    }
+
+    #[test]
+    fn test_expression_statement() {
+        let expected = Statement::Expression {
+            expression: Box::new(Expression::Variable {
+                name: identifier("a", 0),
+            }),
+        };
+        assert_eq!(Ok(vec![expected]), program("a;"));
+    }
+
+    #[test]
+    fn test_var_declaration() {
+        let expected = vec![
+            Statement::Var {
+                name: identifier("a", 0),
+                initializer: None,
+            },
+            Statement::Var {
+                name: identifier("b", 1),
+                initializer: Some(Box::new(Expression::Literal {
+                    value: Float(1.0),
+                    line: 1,
+                })),
+            },
+        ];
+        assert_eq!(Ok(expected), program("var a;\nvar b = 1;"));
+        assert_eq!(Err(Error::MissingVariableName), program("var 1;"));
+        assert_eq!(Err(Error::MissingSemicolon), program("var a = 1"));
+    }
+
+    #[test]
+    fn test_block() {
+        let expected = Statement::Block {
+            statements: vec![
+                Statement::Block { statements: vec![] },
+                Statement::Print {
+                    expression: Box::new(Expression::Literal {
+                        value: True,
+                        line: 0,
+                    }),
+                },
+            ],
+        };
+        assert_eq!(Ok(vec![expected]), program("{ {} print true; }"));
+        assert_eq!(Err(Error::UnclosedBrace), program("{ print true;"));
+    }
+
+    #[test]
+    fn test_assignment() {
+        let expected = Statement::Expression {
+            expression: Box::new(Expression::Assign {
+                name: identifier("a", 0),
+                value: Box::new(Expression::Assign {
+                    name: identifier("b", 0),
+                    value: Box::new(Expression::Literal {
+                        value: Float(1.0),
+                        line: 0,
+                    }),
+                }),
+            }),
+        };
+        assert_eq!(Ok(vec![expected]), program("a = b = 1;"));
+        assert_eq!(Err(Error::InvalidAssignmentTarget), program("a + b = 1;"));
+    }
+
+    #[test]
+    fn test_call() {
+        let expected = Statement::Expression {
+            expression: Box::new(Expression::Call {
+                callee: Box::new(Expression::Call {
+                    callee: Box::new(Expression::Variable {
+                        name: identifier("f", 0),
+                    }),
+                    paren: Token::new(TokenType::RightParen, 1),
+                    arguments: vec![
+                        Expression::Literal {
+                            value: Float(1.0),
+                            line: 0,
+                        },
+                        Expression::Variable {
+                            name: identifier("a", 1),
+                        },
+                    ],
+                }),
+                paren: Token::new(TokenType::RightParen, 1),
+                arguments: vec![],
+            }),
+        };
+        assert_eq!(Ok(vec![expected]), program("f(1,\na)();"));
+        assert_eq!(Err(Error::UnclosedCall), program("f(1;"));
+        assert_eq!(Err(Error::NoExpression), program("f(1,);"));
+
+        let arguments = vec!["1"; MAX_ARGUMENTS].join(", ");
+        assert!(program(&format!("f({});", arguments)).is_ok());
+        assert_eq!(
+            Err(Error::TooManyArguments),
+            program(&format!("f({}, 1);", arguments))
+        );
+    }
+
+    #[test]
+    fn test_repl() {
+        let repl =
+            |source: &str| parse_repl(VecDeque::from(scan_tokens(source.to_string()).unwrap()));
+        let one = || {
+            Box::new(Expression::Literal {
+                value: Float(1.0),
+                line: 0,
+            })
+        };
+
+        assert_eq!(Ok((vec![], Some(one()))), repl("1"));
+        assert_eq!(
+            Ok((vec![Statement::Expression { expression: one() }], None)),
+            repl("1;")
+        );
+        assert_eq!(
+            Ok((
+                vec![Statement::Var {
+                    name: identifier("a", 0),
+                    initializer: Some(one()),
+                }],
+                Some(Box::new(Expression::Variable {
+                    name: identifier("a", 0)
+                }))
+            )),
+            repl("var a = 1; a")
+        );
+        assert_eq!(Ok((vec![], None)), repl(""));
+        assert_eq!(Err((Error::MissingSemicolon, 0)), repl("1 2"));
+        assert_eq!(Err((Error::MissingSemicolon, 1)), repl("print\n1"));
+        assert_eq!(Err((Error::MissingSemicolon, 0)), repl("var a = 1"));
+        // only the prompt leaves semicolons out
+        assert_eq!(Err(Error::MissingSemicolon), program("1"));
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        let parsed = |source: &str| {
+            parse_expression(VecDeque::from(scan_tokens(source.to_string()).unwrap()))
+        };
+        assert_eq!(
+            Ok(Box::new(Expression::Grouping {
+                expression: Box::new(Expression::Literal {
+                    value: Float(1.0),
+                    line: 0
+                })
+            })),
+            parsed("(1)")
+        );
+        assert_eq!(Err((Error::MissingEOF, 1)), parsed("1\n2"));
+        assert_eq!(Err((Error::MissingEOF, 0)), parsed("1;"));
+        assert_eq!(Err((Error::NoExpression, 0)), parsed(""));
+        assert_eq!(Err((Error::UnclosedParen, 2)), parsed("(1\n+\n2"));
+    }
+
+    #[test]
+    fn test_recovering() {
+        let recovering = |source: &str| {
+            parse_program_recovering(VecDeque::from(scan_tokens(source.to_string()).unwrap()))
+        };
+
+        let (statements, errors) = recovering("print 1;\nprint 2");
+        assert_eq!(1, statements.len());
+        assert_eq!(vec![(Error::MissingSemicolon, 1)], errors);
+
+        let (statements, errors) = recovering("var 1;\nprint (2;\nprint 3;\n1 +;\nvar a = 4;");
+        assert_eq!(
+            vec![
+                Statement::Print {
+                    expression: Box::new(Expression::Literal {
+                        value: Float(3.0),
+                        line: 2
+                    }),
+                },
+                Statement::Var {
+                    name: identifier("a", 4),
+                    initializer: Some(Box::new(Expression::Literal {
+                        value: Float(4.0),
+                        line: 4
+                    })),
+                },
+            ],
+            statements
+        );
+        assert_eq!(
+            vec![
+                (Error::MissingVariableName, 0),
+                (Error::UnclosedParen, 1),
+                (Error::NoExpression, 3),
+            ],
+            errors
+        );
+
+        // the statement keyword the last one stopped at starts the next
+        let (statements, errors) = recovering("print 1 print 2;");
+        assert_eq!(1, statements.len());
+        assert_eq!(vec![(Error::MissingSemicolon, 0)], errors);
+
+        // unsupported keywords are skipped rather than stuck on
+        let (statements, errors) = recovering("class; print 1;");
+        assert_eq!(1, statements.len());
+        assert_eq!(vec![(Error::NoExpression, 0)], errors);
+
+        let (statements, errors) = recovering("var");
+        assert!(statements.is_empty());
+        assert_eq!(vec![(Error::MissingVariableName, 0)], errors);
+    }
+
+    #[test]
+    fn test_missing_semicolon() {
+        assert_eq!(Err(Error::MissingSemicolon), program("print 1"));
+        assert_eq!(Err(Error::MissingSemicolon), program("1 2;"));
+    }
 }
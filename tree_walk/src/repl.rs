@@ -0,0 +1,305 @@
+use crate::scan::{self, Source, TokenType, KEYWORDS};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::path::PathBuf;
+
+pub const HELP: &str = "\
+:tokens <source>  show the tokens the scanner produces
+:ast <source>     show the tree the parser produces
+:env              list the global variables
+:load <file>      run a script in this session
+:reset            forget every variable
+:time <source>    run and show how long it took
+:help             show this list";
+
+const COMMANDS: [&str; 7] = ["ast", "env", "help", "load", "reset", "time", "tokens"];
+
+/// A `:` command typed at the prompt instead of Lox.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Tokens(String),
+    Ast(String),
+    Env,
+    Load(PathBuf),
+    Reset,
+    Time(String),
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    UnknownCommand { name: String },
+    MissingArgument { name: String },
+    UnexpectedArgument { name: String },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnknownCommand { name } => write!(f, "unknown command :{}, try :help", name),
+            Error::MissingArgument { name } => write!(f, ":{} needs an argument", name),
+            Error::UnexpectedArgument { name } => write!(f, ":{} takes no argument", name),
+        }
+    }
+}
+
+/// None if the line is Lox rather than a command.
+pub fn parse_command(line: &str) -> Option<Result<Command, Error>> {
+    let line = line.trim().strip_prefix(':')?;
+    let (name, argument) = match line.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (line, ""),
+    };
+    let with_argument = |command: fn(String) -> Command| {
+        if argument.is_empty() {
+            Err(Error::MissingArgument {
+                name: name.to_string(),
+            })
+        } else {
+            Ok(command(argument.to_string()))
+        }
+    };
+    let without_argument = |command: Command| {
+        if argument.is_empty() {
+            Ok(command)
+        } else {
+            Err(Error::UnexpectedArgument {
+                name: name.to_string(),
+            })
+        }
+    };
+    Some(match name {
+        "tokens" => with_argument(Command::Tokens),
+        "ast" => with_argument(Command::Ast),
+        "env" => without_argument(Command::Env),
+        "load" => with_argument(|path| Command::Load(PathBuf::from(path))),
+        "reset" => without_argument(Command::Reset),
+        "time" => with_argument(Command::Time),
+        "help" => without_argument(Command::Help),
+        _ => Err(Error::UnknownCommand {
+            name: name.to_string(),
+        }),
+    })
+}
+
+/// Whether the prompt has a whole entry, or should keep reading lines because
+/// a bracket or string is still open. Anything else wrong with the input
+/// counts as complete, so the error gets reported instead of waited on.
+pub fn is_complete(source: &str) -> bool {
+    let mut depth = 0;
+    for token in Source::new(source.to_string()) {
+        match token {
+            Ok(token) => match token.token_type() {
+                TokenType::LeftParen | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBrace => depth -= 1,
+                _ => {}
+            },
+            Err(scan::Error::UnterminatedString { .. }) => return false,
+            Err(_) => return true,
+        }
+        if depth < 0 {
+            return true;
+        }
+    }
+    depth == 0
+}
+
+/// Where the word being typed at `position` starts, and what it could be:
+/// a command after a leading `:`, otherwise a keyword or one of `globals`.
+/// Nothing is offered after a `.`: member completion waits for classes.
+pub fn complete(line: &str, position: usize, globals: &[String]) -> (usize, Vec<String>) {
+    let before = &line[..position];
+    let start = before
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let prefix = &before[start..];
+    let mut candidates: Vec<String> = if before[..start].trim_start() == ":" {
+        COMMANDS.iter().map(|name| name.to_string()).collect()
+    } else if before[..start].ends_with('.') {
+        // there are no instances yet, so nothing has members to offer
+        vec![]
+    } else {
+        KEYWORDS
+            .iter()
+            .map(|(keyword, _)| keyword.to_string())
+            .chain(globals.iter().cloned())
+            .collect()
+    };
+    candidates.retain(|candidate| candidate.starts_with(prefix));
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+/// Tab completion for the prompt. The names of the globals are copied in
+/// after each entry runs.
+#[derive(Default)]
+pub struct Completion {
+    pub globals: Vec<String>,
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        position: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, position, &self.globals))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(None, parse_command("print 1;"));
+        assert_eq!(
+            Some(Ok(Command::Tokens("1 + 2".to_string()))),
+            parse_command(":tokens 1 + 2")
+        );
+        assert_eq!(
+            Some(Ok(Command::Ast("print (1);".to_string()))),
+            parse_command("  :ast   print (1);  ")
+        );
+        assert_eq!(Some(Ok(Command::Env)), parse_command(":env"));
+        assert_eq!(
+            Some(Ok(Command::Load(PathBuf::from("a b.lox")))),
+            parse_command(":load a b.lox")
+        );
+        assert_eq!(Some(Ok(Command::Reset)), parse_command(":reset\n"));
+        assert_eq!(
+            Some(Ok(Command::Time("print 1;".to_string()))),
+            parse_command(":time\tprint 1;")
+        );
+        assert_eq!(Some(Ok(Command::Help)), parse_command(":help"));
+    }
+
+    #[test]
+    fn test_parse_command_errors() {
+        assert_eq!(
+            Some(Err(Error::UnknownCommand {
+                name: "quit".to_string()
+            })),
+            parse_command(":quit")
+        );
+        assert_eq!(
+            Some(Err(Error::MissingArgument {
+                name: "load".to_string()
+            })),
+            parse_command(":load ")
+        );
+        assert_eq!(
+            Some(Err(Error::UnexpectedArgument {
+                name: "env".to_string()
+            })),
+            parse_command(":env x")
+        );
+        assert_eq!(
+            "unknown command :, try :help",
+            parse_command(":").unwrap().unwrap_err().to_string()
+        );
+    }
+
+    fn completed(line: &str, globals: &[&str]) -> (usize, Vec<String>) {
+        let globals: Vec<String> = globals.iter().map(|name| name.to_string()).collect();
+        complete(line, line.len(), &globals)
+    }
+
+    #[test]
+    fn test_complete_keywords() {
+        assert_eq!((0, vec!["print".to_string()]), completed("pr", &[]));
+        assert_eq!(
+            (
+                6,
+                vec!["false".to_string(), "for".to_string(), "fun".to_string()]
+            ),
+            completed("print f", &[])
+        );
+        assert_eq!((2, vec![]), completed("1 zz", &[]));
+    }
+
+    #[test]
+    fn test_complete_globals() {
+        assert_eq!(
+            (7, vec!["value".to_string(), "var".to_string()]),
+            completed("print (v", &["value", "other"])
+        );
+        assert_eq!(
+            (4, vec!["nil".to_string(), "nine".to_string()]),
+            completed("1 + ni", &["nine"])
+        );
+        // the cursor needn't be at the end
+        let globals = vec!["alpha".to_string()];
+        assert_eq!(
+            (6, vec!["alpha".to_string(), "and".to_string()]),
+            complete("print a;", 7, &globals)
+        );
+    }
+
+    #[test]
+    fn test_complete_commands() {
+        assert_eq!(
+            (1, vec!["time".to_string(), "tokens".to_string()]),
+            completed(":t", &[])
+        );
+        assert_eq!(
+            (1, COMMANDS.map(String::from).to_vec()),
+            completed(":", &[])
+        );
+        // arguments are Lox again
+        assert_eq!(
+            (6, vec!["this".to_string(), "true".to_string()]),
+            completed(":time t", &[])
+        );
+    }
+
+    #[test]
+    fn test_complete_members() {
+        assert_eq!((2, vec![]), completed("a.", &["abc"]));
+        assert_eq!((2, vec![]), completed("a.v", &["var"]));
+    }
+
+    #[test]
+    fn test_complete() {
+        assert!(is_complete(""));
+        assert!(is_complete("print 1;\n"));
+        assert!(is_complete("{ print (1); }"));
+        assert!(is_complete("print \"{\";"));
+        assert!(is_complete("// {"));
+    }
+
+    #[test]
+    fn test_incomplete() {
+        assert!(!is_complete("{"));
+        assert!(!is_complete("{ print (1 +\n"));
+        assert!(!is_complete("{ { } \n"));
+        assert!(!is_complete("print \"a\nb"));
+    }
+
+    #[test]
+    fn test_errors_are_complete() {
+        assert!(is_complete("print 1);"));
+        assert!(is_complete("} {"));
+        assert!(is_complete("{ # "));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::expression::Expression;
+use crate::scan::Token;
+use crate::statement::Statement;
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    ReadInOwnInitializer { name: String, line: usize },
+    AlreadyDeclared { name: String, line: usize },
+}
+
+impl Error {
+    pub fn line(&self) -> usize {
+        match self {
+            Error::ReadInOwnInitializer { line, .. } | Error::AlreadyDeclared { line, .. } => *line,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ReadInOwnInitializer { name, .. } => {
+                write!(
+                    f,
+                    "Can't read local variable '{}' in its own initializer.",
+                    name
+                )
+            }
+            Error::AlreadyDeclared { name, .. } => {
+                write!(f, "Already a variable named '{}' in this scope.", name)
+            }
+        }
+    }
+}
+
+/// Finds the mistakes in how a program uses its local variables. Globals are
+/// left alone, since they can be declared again and used before they're
+/// declared.
+pub fn resolve(statements: &[Statement]) -> Vec<Error> {
+    let mut resolver = Resolver {
+        scopes: Vec::new(),
+        errors: Vec::new(),
+    };
+    resolver.statements(statements);
+    resolver.errors
+}
+
+struct Resolver {
+    // whether each local has finished being defined
+    scopes: Vec<HashMap<String, bool>>,
+    errors: Vec<Error>,
+}
+
+impl Resolver {
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Expression { expression } | Statement::Print { expression } => {
+                self.expression(expression)
+            }
+            Statement::Var { name, initializer } => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.to_string(), true);
+                }
+            }
+            Statement::Block { statements } => {
+                self.scopes.push(HashMap::new());
+                self.statements(statements);
+                self.scopes.pop();
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let scope = match self.scopes.last_mut() {
+            Some(scope) => scope,
+            None => return,
+        };
+        if scope.insert(name.to_string(), false).is_some() {
+            self.errors.push(Error::AlreadyDeclared {
+                name: name.to_string(),
+                line: name.line(),
+            });
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Literal { .. } => {}
+            Expression::Unary { expression, .. } | Expression::Grouping { expression } => {
+                self.expression(expression)
+            }
+            Expression::Binary { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::Variable { name } => {
+                let scope = self.scopes.last();
+                if scope.and_then(|scope| scope.get(&name.to_string())) == Some(&false) {
+                    self.errors.push(Error::ReadInOwnInitializer {
+                        name: name.to_string(),
+                        line: name.line(),
+                    });
+                }
+            }
+            Expression::Assign { value, .. } => self.expression(value),
+            Expression::Call {
+                callee, arguments, ..
+            } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_program;
+    use crate::scan::scan_tokens;
+    use std::collections::VecDeque;
+
+    fn resolved(source: &str) -> Vec<Error> {
+        let tokens = scan_tokens(source.to_string()).unwrap();
+        resolve(&parse_program(VecDeque::from(tokens)).unwrap())
+    }
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(
+            Vec::<Error>::new(),
+            resolved("var a = 1; var a = a; { var b = a; { var b = 1; var c = b; } b = 2; }")
+        );
+    }
+
+    #[test]
+    fn test_read_in_own_initializer() {
+        assert_eq!(
+            vec![Error::ReadInOwnInitializer {
+                name: "a".to_string(),
+                line: 1
+            }],
+            resolved("{\n  var a = -a;\n}")
+        );
+    }
+
+    #[test]
+    fn test_already_declared() {
+        let errors = resolved("{ var a; {} var a = 1; }");
+        assert_eq!(
+            vec![Error::AlreadyDeclared {
+                name: "a".to_string(),
+                line: 0
+            }],
+            errors
+        );
+        assert_eq!(
+            "Already a variable named 'a' in this scope.",
+            errors[0].to_string()
+        );
+    }
+}
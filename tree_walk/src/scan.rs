@@ -1,5 +1,24 @@
 use std::collections::VecDeque;
 
+pub const KEYWORDS: [(&str, TokenType); 16] = [
+    ("and", TokenType::And),
+    ("class", TokenType::Class),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("for", TokenType::For),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("true", TokenType::True),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+];
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum TokenType {
     // single-character tokens
@@ -48,23 +67,55 @@ pub enum TokenType {
     Var,
     While,
 
+    // trivia, only produced by Source::with_trivia
+    Whitespace(String),
+    Comment(String),
+
     EOF,
 }
 
-#[derive(Debug, PartialEq)]
+/// Byte offsets of a token within the source it was scanned from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Debug)]
 pub struct Token {
     type_: TokenType,
     line: usize,
+    span: Span,
 }
 
 impl Token {
     pub fn new(type_: TokenType, line: usize) -> Self {
-        Self { type_, line }
+        Self {
+            type_,
+            line,
+            span: Span::default(),
+        }
     }
 
     pub fn token_type(&self) -> TokenType {
         self.type_.clone()
     }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+// The span only records where a token came from, so tokens built by hand
+// compare equal to scanned ones.
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.type_ == other.type_ && self.line == other.line
+    }
 }
 
 impl std::fmt::Display for Token {
@@ -108,15 +159,19 @@ impl std::fmt::Display for Token {
             TokenType::True => write!(f, "true"),
             TokenType::Var => write!(f, "var"),
             TokenType::While => write!(f, "while"),
+            TokenType::Whitespace(string) => write!(f, "{}", string),
+            TokenType::Comment(string) => write!(f, "{}", string),
             TokenType::EOF => write!(f, "EOF"),
         }
     }
 }
 
-struct Source {
+pub struct Source {
     text: VecDeque<char>,
     line: usize,
+    offset: usize,
     eof_sent: bool,
+    trivia: bool,
 }
 
 impl Source {
@@ -128,80 +183,123 @@ impl Source {
         Self {
             text,
             line: 0,
+            offset: 0,
             eof_sent: false,
+            trivia: false,
+        }
+    }
+
+    /// A source that also yields whitespace and comments as tokens, so that
+    /// concatenating the spans of everything it produces gives back the input.
+    pub fn with_trivia(source: String) -> Self {
+        Self {
+            trivia: true,
+            ..Self::new(source)
         }
     }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.text.pop_front()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+
+    fn token(&self, type_: TokenType, line: usize, start: usize) -> Token {
+        Token {
+            type_,
+            line,
+            span: Span {
+                start,
+                end: self.offset,
+            },
+        }
+    }
+
+    fn whitespace(&mut self) -> TokenType {
+        let mut literal = String::new();
+        while let Some(c @ ' ' | c @ '\r' | c @ '\t' | c @ '\n') = self.text.front() {
+            if *c == '\n' {
+                self.line += 1;
+            }
+            literal.push(*c);
+            self.advance();
+        }
+        TokenType::Whitespace(literal)
+    }
+
+    fn comment(&mut self) -> TokenType {
+        let mut literal = String::new();
+        while !self.text.is_empty() && self.text.front() != Some(&'\n') {
+            literal.extend(self.advance());
+        }
+        TokenType::Comment(literal)
+    }
 }
 
 impl Iterator for Source {
     type Item = Result<Token, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.text.len() > 1 && self.text[0] == '/' && self.text[1] == '/' {
-            while !self.text.is_empty() && self.text.front() != Some(&'\n') {
-                self.text.pop_front();
-            }
-        }
-
         loop {
-            match self.text.front() {
-                Some('\n') => {
-                    self.line += 1;
-                    self.text.pop_front();
-                }
-                Some(' ') | Some('\r') | Some('\t') => {
-                    self.text.pop_front();
-                }
+            let start = self.offset;
+            let line = self.line;
+            let trivia = match self.text.front() {
+                Some(' ') | Some('\r') | Some('\t') | Some('\n') => self.whitespace(),
+                Some('/') if self.text.get(1) == Some(&'/') => self.comment(),
                 _ => {
                     break;
                 }
+            };
+            if self.trivia {
+                return Some(Ok(self.token(trivia, line, start)));
             }
         }
 
-        match self.text.pop_front() {
-            Some('(') => Some(Ok(Token::new(TokenType::LeftParen, self.line))),
-            Some(')') => Some(Ok(Token::new(TokenType::RightParen, self.line))),
-            Some('{') => Some(Ok(Token::new(TokenType::LeftBrace, self.line))),
-            Some('}') => Some(Ok(Token::new(TokenType::RightBrace, self.line))),
-            Some(',') => Some(Ok(Token::new(TokenType::Comma, self.line))),
-            Some('.') => Some(Ok(Token::new(TokenType::Dot, self.line))),
-            Some('-') => Some(Ok(Token::new(TokenType::Minus, self.line))),
-            Some('+') => Some(Ok(Token::new(TokenType::Plus, self.line))),
-            Some(';') => Some(Ok(Token::new(TokenType::Semicolon, self.line))),
-            Some('*') => Some(Ok(Token::new(TokenType::Star, self.line))),
+        let start = self.offset;
+        let type_ = match self.advance() {
+            Some('(') => TokenType::LeftParen,
+            Some(')') => TokenType::RightParen,
+            Some('{') => TokenType::LeftBrace,
+            Some('}') => TokenType::RightBrace,
+            Some(',') => TokenType::Comma,
+            Some('.') => TokenType::Dot,
+            Some('-') => TokenType::Minus,
+            Some('+') => TokenType::Plus,
+            Some(';') => TokenType::Semicolon,
+            Some('*') => TokenType::Star,
             Some('!') => match self.text.front() {
                 Some('=') => {
-                    self.text.pop_front();
-                    Some(Ok(Token::new(TokenType::BangEqual, self.line)))
+                    self.advance();
+                    TokenType::BangEqual
                 }
-                _ => Some(Ok(Token::new(TokenType::Bang, self.line))),
+                _ => TokenType::Bang,
             },
             Some('=') => match self.text.front() {
                 Some('=') => {
-                    self.text.pop_front();
-                    Some(Ok(Token::new(TokenType::EqualEqual, self.line)))
+                    self.advance();
+                    TokenType::EqualEqual
                 }
-                _ => Some(Ok(Token::new(TokenType::Equal, self.line))),
+                _ => TokenType::Equal,
             },
             Some('>') => match self.text.front() {
                 Some('=') => {
-                    self.text.pop_front();
-                    Some(Ok(Token::new(TokenType::GreaterEqual, self.line)))
+                    self.advance();
+                    TokenType::GreaterEqual
                 }
-                _ => Some(Ok(Token::new(TokenType::Greater, self.line))),
+                _ => TokenType::Greater,
             },
             Some('<') => match self.text.front() {
                 Some('=') => {
-                    self.text.pop_front();
-                    Some(Ok(Token::new(TokenType::LessEqual, self.line)))
+                    self.advance();
+                    TokenType::LessEqual
                 }
-                _ => Some(Ok(Token::new(TokenType::Less, self.line))),
+                _ => TokenType::Less,
             },
-            Some('/') => Some(Ok(Token::new(TokenType::Slash, self.line))),
+            Some('/') => TokenType::Slash,
             Some('"') => {
                 let mut literal = String::new();
                 loop {
-                    match self.text.pop_front() {
+                    match self.advance() {
                         Some('\n') => {
                             self.line += 1;
                         }
@@ -216,20 +314,22 @@ impl Iterator for Source {
                         }
                     }
                 }
-                Some(Ok(Token::new(TokenType::Str(literal), self.line)))
+                TokenType::Str(literal)
             }
             Some(c @ '0'..='9') => {
                 let mut literal = String::from(c);
                 while let Some(c @ '0'..='9' | c @ '.') = self.text.front() {
                     literal.push(*c);
-                    self.text.pop_front();
+                    self.advance();
                 }
                 match literal.parse::<f32>() {
-                    Ok(n) => Some(Ok(Token::new(TokenType::Number(n), self.line))),
-                    _ => Some(Err(Error::NumberParse {
-                        literal,
-                        line: self.line,
-                    })),
+                    Ok(n) => TokenType::Number(n),
+                    _ => {
+                        return Some(Err(Error::NumberParse {
+                            literal,
+                            line: self.line,
+                        }))
+                    }
                 }
             }
             Some(c @ 'a'..='z' | c @ 'A'..='Z' | c @ '_') => {
@@ -238,38 +338,23 @@ impl Iterator for Source {
                     self.text.front()
                 {
                     literal.push(*c);
-                    self.text.pop_front();
+                    self.advance();
                 }
-                match &literal[..] {
-                    "and" => Some(Ok(Token::new(TokenType::And, self.line))),
-                    "class" => Some(Ok(Token::new(TokenType::Class, self.line))),
-                    "else" => Some(Ok(Token::new(TokenType::Else, self.line))),
-                    "false" => Some(Ok(Token::new(TokenType::False, self.line))),
-                    "for" => Some(Ok(Token::new(TokenType::For, self.line))),
-                    "fun" => Some(Ok(Token::new(TokenType::Fun, self.line))),
-                    "if" => Some(Ok(Token::new(TokenType::If, self.line))),
-                    "nil" => Some(Ok(Token::new(TokenType::Nil, self.line))),
-                    "or" => Some(Ok(Token::new(TokenType::Or, self.line))),
-                    "print" => Some(Ok(Token::new(TokenType::Print, self.line))),
-                    "return" => Some(Ok(Token::new(TokenType::Return, self.line))),
-                    "super" => Some(Ok(Token::new(TokenType::Super, self.line))),
-                    "this" => Some(Ok(Token::new(TokenType::This, self.line))),
-                    "true" => Some(Ok(Token::new(TokenType::True, self.line))),
-                    "var" => Some(Ok(Token::new(TokenType::Var, self.line))),
-                    "while" => Some(Ok(Token::new(TokenType::While, self.line))),
-                    _ => Some(Ok(Token::new(TokenType::Identifier(literal), self.line))),
+                match KEYWORDS.iter().find(|(keyword, _)| *keyword == literal) {
+                    Some((_, type_)) => type_.clone(),
+                    None => TokenType::Identifier(literal),
                 }
             }
-            Some(c) => Some(Err(Error::BadChar { c, line: self.line })),
+            Some(c) => return Some(Err(Error::BadChar { c, line: self.line })),
             None => {
-                if !self.eof_sent {
-                    self.eof_sent = true;
-                    Some(Ok(Token::new(TokenType::EOF, self.line)))
-                } else {
-                    None
+                if self.eof_sent {
+                    return None;
                 }
+                self.eof_sent = true;
+                TokenType::EOF
             }
-        }
+        };
+        Some(Ok(self.token(type_, self.line, start)))
     }
 }
 
@@ -280,6 +365,26 @@ pub enum Error {
     NumberParse { literal: String, line: usize },
 }
 
+impl Error {
+    pub fn line(&self) -> usize {
+        match self {
+            Error::BadChar { line, .. }
+            | Error::UnterminatedString { line }
+            | Error::NumberParse { line, .. } => *line,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::BadChar { c, .. } => write!(f, "Unexpected character '{}'.", c),
+            Error::UnterminatedString { .. } => write!(f, "Unterminated string."),
+            Error::NumberParse { literal, .. } => write!(f, "Invalid number '{}'.", literal),
+        }
+    }
+}
+
 pub fn scan_tokens(source: String) -> Result<Vec<Token>, Error> {
     let tokenizer = Source::new(source);
     tokenizer.collect()
@@ -403,6 +508,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_comment_after_token() {
+        assert_eq!(
+            Ok(vec![
+                Token::new(TokenType::Number(1.0), 0),
+                Token::new(TokenType::Number(2.0), 1),
+                Token::new(TokenType::EOF, 1)
+            ]),
+            scan_tokens("1 // one\n2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let tokens = scan_tokens("foo >= \"é\"".to_string()).unwrap();
+        let spans: Vec<Span> = tokens.iter().map(Token::span).collect();
+        assert_eq!(
+            vec![
+                Span { start: 0, end: 3 },
+                Span { start: 4, end: 6 },
+                Span { start: 7, end: 11 },
+                Span { start: 11, end: 11 },
+            ],
+            spans
+        );
+    }
+
+    #[test]
+    fn test_trivia() {
+        let source = "1 // one\n\t2".to_string();
+        let tokens: Result<Vec<Token>, Error> = Source::with_trivia(source.clone()).collect();
+        assert_eq!(
+            Ok(vec![
+                Token::new(TokenType::Number(1.0), 0),
+                Token::new(TokenType::Whitespace(" ".to_string()), 0),
+                Token::new(TokenType::Comment("// one".to_string()), 0),
+                Token::new(TokenType::Whitespace("\n\t".to_string()), 0),
+                Token::new(TokenType::Number(2.0), 1),
+                Token::new(TokenType::EOF, 1)
+            ]),
+            tokens
+        );
+        let text: String = tokens
+            .unwrap()
+            .iter()
+            .map(|token| &source[token.span().start..token.span().end])
+            .collect();
+        assert_eq!(source, text);
+    }
+
     #[test]
     fn test_bad_character() {
         assert_eq!(
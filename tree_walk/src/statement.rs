@@ -0,0 +1,19 @@
+use crate::expression::Expression;
+use crate::scan::Token;
+
+#[derive(Debug, PartialEq)]
+pub enum Statement {
+    Expression {
+        expression: Box<Expression>,
+    },
+    Print {
+        expression: Box<Expression>,
+    },
+    Var {
+        name: Token,
+        initializer: Option<Box<Expression>>,
+    },
+    Block {
+        statements: Vec<Statement>,
+    },
+}
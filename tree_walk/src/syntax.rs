@@ -0,0 +1,825 @@
+//! A lossless concrete syntax tree.
+//!
+//! The green tree is immutable and only knows the width of each node, so
+//! subtrees can be shared and rebuilt cheaply. The red tree (`SyntaxNode`,
+//! `SyntaxToken`) is a thin view over it that adds absolute offsets and parent
+//! links. Every byte of the input, including whitespace, comments and text the
+//! scanner rejected, lives in exactly one leaf, so printing a tree gives back
+//! the source it was parsed from.
+
+use crate::parse;
+use crate::scan::{self, Source, Span, TokenType};
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NodeKind {
+    Program,
+    Var,
+    Print,
+    ExpressionStatement,
+    Block,
+    Assign,
+    Variable,
+    Literal,
+    Unary,
+    Binary,
+    Grouping,
+    Call,
+    Error,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GreenToken {
+    // None for text the scanner could not turn into a token
+    token_type: Option<TokenType>,
+    text: String,
+}
+
+impl GreenToken {
+    pub fn new(token_type: Option<TokenType>, text: String) -> Self {
+        Self { token_type, text }
+    }
+
+    pub fn token_type(&self) -> Option<&TokenType> {
+        self.token_type.as_ref()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self.token_type,
+            Some(TokenType::Whitespace(_)) | Some(TokenType::Comment(_))
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GreenNode {
+    kind: NodeKind,
+    width: usize,
+    children: Vec<GreenElement>,
+}
+
+impl GreenNode {
+    pub fn new(kind: NodeKind, children: Vec<GreenElement>) -> Self {
+        let width = children.iter().map(GreenElement::width).sum();
+        Self {
+            kind,
+            width,
+            children,
+        }
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+}
+
+impl std::fmt::Display for GreenNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for child in &self.children {
+            write!(f, "{}", child)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn width(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.width,
+            GreenElement::Token(token) => token.text.len(),
+        }
+    }
+}
+
+impl std::fmt::Display for GreenElement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GreenElement::Node(node) => write!(f, "{}", node),
+            GreenElement::Token(token) => write!(f, "{}", token.text),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+#[derive(Debug)]
+struct NodeData {
+    green: Rc<GreenNode>,
+    offset: usize,
+    parent: Option<SyntaxNode>,
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>) -> Self {
+        Self(Rc::new(NodeData {
+            green,
+            offset: 0,
+            parent: None,
+        }))
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.0.offset,
+            end: self.0.offset + self.0.green.width,
+        }
+    }
+
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    pub fn children(&self) -> Vec<SyntaxElement> {
+        let mut offset = self.0.offset;
+        let mut children = Vec::new();
+        for child in &self.0.green.children {
+            children.push(match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    offset,
+                    parent: Some(self.clone()),
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    offset,
+                    parent: self.clone(),
+                }),
+            });
+            offset += child.width();
+        }
+        children
+    }
+
+    pub fn child_nodes(&self) -> Vec<SyntaxNode> {
+        self.children()
+            .into_iter()
+            .filter_map(|child| match child {
+                SyntaxElement::Node(node) => Some(node),
+                SyntaxElement::Token(_) => None,
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.green)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    offset: usize,
+    parent: SyntaxNode,
+}
+
+impl SyntaxToken {
+    pub fn token_type(&self) -> Option<&TokenType> {
+        self.green.token_type()
+    }
+
+    pub fn text(&self) -> &str {
+        self.green.text()
+    }
+
+    pub fn is_trivia(&self) -> bool {
+        self.green.is_trivia()
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset + self.green.text.len(),
+        }
+    }
+
+    pub fn parent(&self) -> SyntaxNode {
+        self.parent.clone()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Scan(scan::Error),
+    Parse { error: parse::Error, span: Span },
+}
+
+impl Error {
+    /// The line of the source the tree was parsed from that the error is on.
+    pub fn line(&self, source: &str) -> usize {
+        match self {
+            Error::Scan(error) => error.line(),
+            Error::Parse { span, .. } => source
+                .bytes()
+                .take(span.start)
+                .filter(|byte| *byte == b'\n')
+                .count(),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Scan(error) => write!(f, "{}", error),
+            Error::Parse { error, .. } => write!(f, "{}", error),
+        }
+    }
+}
+
+/// The result of parsing: a tree that always covers the whole input, and
+/// whatever went wrong while building it.
+#[derive(Debug)]
+pub struct SyntaxTree {
+    green: Rc<GreenNode>,
+    errors: Vec<Error>,
+}
+
+impl SyntaxTree {
+    pub fn root(&self) -> SyntaxNode {
+        SyntaxNode::new_root(self.green.clone())
+    }
+
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    pub fn into_errors(self) -> Vec<Error> {
+        self.errors
+    }
+}
+
+pub fn parse(source: String) -> SyntaxTree {
+    let mut errors = Vec::new();
+    let lexemes = lex(&source, &mut errors);
+    let mut parser = Parser {
+        lexemes,
+        position: 0,
+        offset: 0,
+        stack: Vec::new(),
+        root: None,
+        errors,
+    };
+    program(&mut parser);
+    SyntaxTree {
+        green: parser.root.expect("program always finishes its node"),
+        errors: parser.errors,
+    }
+}
+
+fn lex(source: &str, errors: &mut Vec<Error>) -> Vec<GreenToken> {
+    let mut lexemes = Vec::new();
+    let mut cursor = 0;
+    for result in Source::with_trivia(source.to_string()) {
+        match result {
+            Ok(token) => {
+                let span = token.span();
+                if span.start > cursor {
+                    lexemes.push(GreenToken::new(
+                        None,
+                        source[cursor..span.start].to_string(),
+                    ));
+                }
+                lexemes.push(GreenToken::new(
+                    Some(token.token_type()),
+                    source[span.start..span.end].to_string(),
+                ));
+                cursor = span.end;
+            }
+            Err(error) => errors.push(Error::Scan(error)),
+        }
+    }
+    lexemes
+}
+
+struct Parser {
+    lexemes: Vec<GreenToken>,
+    position: usize,
+    offset: usize,
+    stack: Vec<(NodeKind, Vec<GreenElement>)>,
+    root: Option<Rc<GreenNode>>,
+    errors: Vec<Error>,
+}
+
+impl Parser {
+    // rejected text is reported by the scanner, so the grammar steps over it
+    // just like whitespace
+    fn skipped(lexeme: &GreenToken) -> bool {
+        lexeme.token_type.is_none() || lexeme.is_trivia()
+    }
+
+    fn lookahead(&self) -> usize {
+        let mut position = self.position;
+        while position < self.lexemes.len() && Self::skipped(&self.lexemes[position]) {
+            position += 1;
+        }
+        position
+    }
+
+    fn peek(&self) -> &TokenType {
+        match self.lexemes.get(self.lookahead()) {
+            Some(GreenToken {
+                token_type: Some(token_type),
+                ..
+            }) => token_type,
+            _ => &TokenType::EOF,
+        }
+    }
+
+    fn push(&mut self, element: GreenElement) {
+        self.stack
+            .last_mut()
+            .expect("tokens are only bumped inside a node")
+            .1
+            .push(element);
+    }
+
+    fn eat_trivia(&mut self) {
+        while self.position < self.lookahead() {
+            self.bump_raw();
+        }
+    }
+
+    fn bump_raw(&mut self) {
+        let lexeme = std::mem::replace(
+            &mut self.lexemes[self.position],
+            GreenToken::new(None, String::new()),
+        );
+        self.position += 1;
+        self.offset += lexeme.text.len();
+        self.push(GreenElement::Token(Rc::new(lexeme)));
+    }
+
+    fn bump(&mut self) {
+        self.eat_trivia();
+        if self.position < self.lexemes.len() {
+            self.bump_raw();
+        }
+    }
+
+    fn start(&mut self, kind: NodeKind) {
+        if !self.stack.is_empty() {
+            self.eat_trivia();
+        }
+        self.stack.push((kind, Vec::new()));
+    }
+
+    fn checkpoint(&mut self) -> usize {
+        self.eat_trivia();
+        self.stack.last().map_or(0, |(_, children)| children.len())
+    }
+
+    fn start_at(&mut self, checkpoint: usize, kind: NodeKind) {
+        let children = match self.stack.last_mut() {
+            Some((_, children)) => children.split_off(checkpoint),
+            None => Vec::new(),
+        };
+        self.stack.push((kind, children));
+    }
+
+    fn finish(&mut self) {
+        let (kind, children) = self.stack.pop().expect("finish matches a start");
+        let node = Rc::new(GreenNode::new(kind, children));
+        if self.stack.is_empty() {
+            self.root = Some(node);
+        } else {
+            self.push(GreenElement::Node(node));
+        }
+    }
+
+    fn error(&mut self, error: parse::Error) {
+        let mut start = self.offset;
+        for lexeme in &self.lexemes[self.position..self.lookahead()] {
+            start += lexeme.text.len();
+        }
+        let end = start
+            + self
+                .lexemes
+                .get(self.lookahead())
+                .map_or(0, |lexeme| lexeme.text.len());
+        self.errors.push(Error::Parse {
+            error,
+            span: Span { start, end },
+        });
+    }
+}
+
+fn program(parser: &mut Parser) {
+    parser.start(NodeKind::Program);
+    while *parser.peek() != TokenType::EOF {
+        declaration(parser);
+    }
+    parser.bump();
+    parser.finish();
+}
+
+fn expect(parser: &mut Parser, type_: TokenType, error: parse::Error) {
+    if *parser.peek() == type_ {
+        parser.bump();
+    } else {
+        parser.error(error);
+    }
+}
+
+fn declaration(parser: &mut Parser) {
+    if *parser.peek() == TokenType::Var {
+        parser.start(NodeKind::Var);
+        parser.bump();
+        if matches!(parser.peek(), TokenType::Identifier(_)) {
+            parser.bump();
+        } else {
+            parser.error(parse::Error::MissingVariableName);
+        }
+        if *parser.peek() == TokenType::Equal {
+            parser.bump();
+            expression(parser);
+        }
+        expect(parser, TokenType::Semicolon, parse::Error::MissingSemicolon);
+        parser.finish();
+    } else {
+        statement(parser);
+    }
+}
+
+fn statement(parser: &mut Parser) {
+    match parser.peek() {
+        TokenType::Print => {
+            parser.start(NodeKind::Print);
+            parser.bump();
+            expression(parser);
+            expect(parser, TokenType::Semicolon, parse::Error::MissingSemicolon);
+            parser.finish();
+        }
+        TokenType::LeftBrace => {
+            parser.start(NodeKind::Block);
+            parser.bump();
+            while ![TokenType::RightBrace, TokenType::EOF].contains(parser.peek()) {
+                declaration(parser);
+            }
+            expect(parser, TokenType::RightBrace, parse::Error::UnclosedBrace);
+            parser.finish();
+        }
+        _ => {
+            parser.start(NodeKind::ExpressionStatement);
+            expression(parser);
+            expect(parser, TokenType::Semicolon, parse::Error::MissingSemicolon);
+            parser.finish();
+        }
+    }
+}
+
+fn expression(parser: &mut Parser) {
+    assignment(parser)
+}
+
+fn assignment(parser: &mut Parser) {
+    let checkpoint = parser.checkpoint();
+    equality(parser);
+    if *parser.peek() == TokenType::Equal {
+        let target = match parser.stack.last() {
+            Some((_, children)) => children.last(),
+            None => None,
+        };
+        if !matches!(target, Some(GreenElement::Node(node)) if node.kind == NodeKind::Variable) {
+            parser.error(parse::Error::InvalidAssignmentTarget);
+        }
+        parser.start_at(checkpoint, NodeKind::Assign);
+        parser.bump();
+        assignment(parser);
+        parser.finish();
+    }
+}
+
+fn binary(parser: &mut Parser, operand: fn(&mut Parser), operators: &[TokenType]) {
+    let checkpoint = parser.checkpoint();
+    operand(parser);
+    while operators.contains(parser.peek()) {
+        parser.start_at(checkpoint, NodeKind::Binary);
+        parser.bump();
+        operand(parser);
+        parser.finish();
+    }
+}
+
+fn equality(parser: &mut Parser) {
+    binary(
+        parser,
+        comparison,
+        &[TokenType::EqualEqual, TokenType::BangEqual],
+    )
+}
+
+fn comparison(parser: &mut Parser) {
+    binary(
+        parser,
+        term,
+        &[
+            TokenType::Greater,
+            TokenType::GreaterEqual,
+            TokenType::Less,
+            TokenType::LessEqual,
+        ],
+    )
+}
+
+fn term(parser: &mut Parser) {
+    binary(parser, factor, &[TokenType::Minus, TokenType::Plus])
+}
+
+fn factor(parser: &mut Parser) {
+    binary(parser, unary, &[TokenType::Slash, TokenType::Star])
+}
+
+fn unary(parser: &mut Parser) {
+    if [TokenType::Minus, TokenType::Bang].contains(parser.peek()) {
+        parser.start(NodeKind::Unary);
+        parser.bump();
+        unary(parser);
+        parser.finish();
+    } else {
+        call(parser)
+    }
+}
+
+fn call(parser: &mut Parser) {
+    let checkpoint = parser.checkpoint();
+    primary(parser);
+    while *parser.peek() == TokenType::LeftParen {
+        parser.start_at(checkpoint, NodeKind::Call);
+        parser.bump();
+        if *parser.peek() != TokenType::RightParen {
+            let mut count = 0;
+            loop {
+                if count == parse::MAX_ARGUMENTS {
+                    parser.error(parse::Error::TooManyArguments);
+                }
+                expression(parser);
+                count += 1;
+                if *parser.peek() != TokenType::Comma {
+                    break;
+                }
+                parser.bump();
+            }
+        }
+        expect(parser, TokenType::RightParen, parse::Error::UnclosedCall);
+        parser.finish();
+    }
+}
+
+fn primary(parser: &mut Parser) {
+    match parser.peek() {
+        TokenType::Number(_)
+        | TokenType::Str(_)
+        | TokenType::True
+        | TokenType::False
+        | TokenType::Nil => {
+            parser.start(NodeKind::Literal);
+            parser.bump();
+            parser.finish();
+        }
+        TokenType::Identifier(_) => {
+            parser.start(NodeKind::Variable);
+            parser.bump();
+            parser.finish();
+        }
+        TokenType::LeftParen => {
+            parser.start(NodeKind::Grouping);
+            parser.bump();
+            expression(parser);
+            if *parser.peek() == TokenType::RightParen {
+                parser.bump();
+            } else {
+                parser.error(parse::Error::UnclosedParen);
+            }
+            parser.finish();
+        }
+        // leave the semicolon to end the statement
+        TokenType::EOF | TokenType::Semicolon => parser.error(parse::Error::NoExpression),
+        _ => {
+            parser.error(parse::Error::NoExpression);
+            parser.start(NodeKind::Error);
+            parser.bump();
+            parser.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(source: &str) {
+        let tree = parse(source.to_string());
+        assert_eq!(source, tree.root().to_string());
+        assert_eq!(source.len(), tree.root().span().end);
+    }
+
+    fn shape(node: &SyntaxNode) -> String {
+        let children: Vec<String> = node.child_nodes().iter().map(shape).collect();
+        if children.is_empty() {
+            format!("{:?}", node.kind())
+        } else {
+            format!("{:?}({})", node.kind(), children.join(" "))
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip("");
+        round_trip("print 1 + 2;");
+        round_trip("  (1 +\t2) * 3;\r\n");
+        round_trip("// leading comment\nvar a = -5; // trailing comment\n");
+        round_trip("{\n    a = \"multi\nline\" == \"string\";\n}\n");
+        round_trip("1.50 != 1.5;");
+    }
+
+    #[test]
+    fn test_round_trip_invalid_input() {
+        round_trip("1 + ");
+        round_trip("(1 + 2");
+        round_trip("1 & 2;");
+        round_trip("1 + \"unterminated\n");
+        round_trip("héllo → wörld");
+        round_trip(") ) var");
+        round_trip("{ print");
+        round_trip("f(1,\n  2)(");
+        round_trip("f(,);");
+    }
+
+    #[test]
+    fn test_structure() {
+        let tree = parse("print -1 + (2 * 3); // done".to_string());
+        assert_eq!(Vec::<Error>::new(), tree.errors());
+        assert_eq!(
+            "Program(Print(Binary(Unary(Literal) Grouping(Binary(Literal Literal)))))",
+            shape(&tree.root())
+        );
+
+        let tree = parse("var a; { a = b = nil; }".to_string());
+        assert_eq!(Vec::<Error>::new(), tree.errors());
+        assert_eq!(
+            "Program(Var Block(ExpressionStatement(Assign(Variable Assign(Variable Literal)))))",
+            shape(&tree.root())
+        );
+
+        let tree = parse("print f(1, g())(2);".to_string());
+        assert_eq!(Vec::<Error>::new(), tree.errors());
+        assert_eq!(
+            "Program(Print(Call(Call(Variable Literal Call(Variable)) Literal)))",
+            shape(&tree.root())
+        );
+    }
+
+    #[test]
+    fn test_trivia_belongs_to_enclosing_node() {
+        let tree = parse("  1;\n".to_string());
+        let children = tree.root().children();
+        match &children[0] {
+            SyntaxElement::Token(token) => {
+                assert!(token.is_trivia());
+                assert_eq!("  ", token.text());
+            }
+            _ => panic!("expected leading whitespace"),
+        }
+        match &children[1] {
+            SyntaxElement::Node(node) => {
+                assert_eq!(NodeKind::ExpressionStatement, node.kind());
+                assert_eq!("1;", node.to_string());
+            }
+            _ => panic!("expected statement"),
+        }
+    }
+
+    #[test]
+    fn test_offsets_and_parents() {
+        let tree = parse("1 + 23;".to_string());
+        let statement = tree.root().child_nodes().remove(0);
+        let binary = statement.child_nodes().remove(0);
+        let right = binary.child_nodes().remove(1);
+        assert_eq!(Span { start: 4, end: 6 }, right.span());
+        assert_eq!("23", right.to_string());
+        assert_eq!(NodeKind::Binary, right.parent().unwrap().kind());
+        match &right.children()[0] {
+            SyntaxElement::Token(token) => {
+                assert_eq!(Some(&TokenType::Number(23.0)), token.token_type());
+                assert_eq!(Span { start: 4, end: 6 }, token.span());
+                assert_eq!(NodeKind::Literal, token.parent().kind());
+            }
+            _ => panic!("expected number token"),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let tree = parse("(1 + 2;".to_string());
+        assert_eq!(
+            &[Error::Parse {
+                error: parse::Error::UnclosedParen,
+                span: Span { start: 6, end: 7 },
+            }],
+            tree.errors()
+        );
+
+        let tree = parse("1 & 2;".to_string());
+        assert_eq!(
+            &[
+                Error::Scan(scan::Error::BadChar { c: '&', line: 0 }),
+                Error::Parse {
+                    error: parse::Error::MissingSemicolon,
+                    span: Span { start: 4, end: 5 },
+                }
+            ],
+            tree.errors()
+        );
+        assert_eq!(
+            "Program(ExpressionStatement(Literal) ExpressionStatement(Literal))",
+            shape(&tree.root())
+        );
+
+        let tree = parse(";".to_string());
+        assert_eq!(
+            &[Error::Parse {
+                error: parse::Error::NoExpression,
+                span: Span { start: 0, end: 1 },
+            }],
+            tree.errors()
+        );
+        assert_eq!("Program(ExpressionStatement)", shape(&tree.root()));
+
+        let tree = parse("print };".to_string());
+        assert_eq!(
+            &[Error::Parse {
+                error: parse::Error::NoExpression,
+                span: Span { start: 6, end: 7 },
+            }],
+            tree.errors()
+        );
+        assert_eq!("Program(Print(Error))", shape(&tree.root()));
+
+        let tree = parse("1 = 2;".to_string());
+        assert_eq!(
+            &[Error::Parse {
+                error: parse::Error::InvalidAssignmentTarget,
+                span: Span { start: 2, end: 3 },
+            }],
+            tree.errors()
+        );
+        assert_eq!(
+            "Program(ExpressionStatement(Assign(Literal Literal)))",
+            shape(&tree.root())
+        );
+    }
+
+    #[test]
+    fn test_error_lines() {
+        let source = "1;\n\n(1 + 2;\n&";
+        let tree = parse(source.to_string());
+        let reported: Vec<String> = tree
+            .errors()
+            .iter()
+            .map(|error| format!("{}: {}", error.line(source), error))
+            .collect();
+        assert_eq!(
+            vec![
+                "3: Unexpected character '&'.",
+                "2: Expect ')' after expression."
+            ],
+            reported
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use std::process::Command;
+
+fn printed(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_tree_walk"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn test_script_args() {
+    let script = std::env::temp_dir().join(format!("args-{}.lox", std::process::id()));
+    std::fs::write(&script, "print args;\n").unwrap();
+    let script = script.to_str().unwrap();
+    assert_eq!("[--flag, x]\n", printed(&[script, "--flag", "x"]));
+    assert_eq!("[-e, --optimize]\n", printed(&[script, "-e", "--optimize"]));
+    assert_eq!(
+        "[--flag]\n",
+        printed(&["--optimize", script, "--", "--flag"])
+    );
+    std::fs::remove_file(script).unwrap();
+}
+
+#[test]
+fn test_eval_args() {
+    assert_eq!("[--flag]\n", printed(&["-e", "print args;", "--flag"]));
+    assert_eq!(
+        "[a, --flag, -x]\n",
+        printed(&["--eval=print args;", "a", "--flag", "-x"])
+    );
+    assert_eq!(
+        "[--flag]\n",
+        printed(&["-e", "print args;", "--", "--flag"])
+    );
+}
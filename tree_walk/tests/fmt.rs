@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::PathBuf;
+
+use tree_walk::format::{format, Config};
+use tree_walk::scan::{scan_tokens, TokenType};
+
+fn corpus() -> Vec<(PathBuf, String)> {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut scripts: Vec<(PathBuf, String)> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+        .map(|path| {
+            let source = fs::read_to_string(&path).unwrap();
+            (path, source)
+        })
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+    scripts
+}
+
+fn token_types(source: &str) -> Vec<TokenType> {
+    scan_tokens(source.to_string())
+        .unwrap()
+        .iter()
+        .map(|token| token.token_type())
+        .collect()
+}
+
+#[test]
+fn test_formatting_is_idempotent() {
+    for width in [20, 40, 80] {
+        let config = Config { width };
+        for (path, source) in corpus() {
+            let once = format(source, &config).unwrap();
+            let twice = format(once.clone(), &config).unwrap();
+            assert_eq!(once, twice, "{} at width {}", path.display(), width);
+        }
+    }
+}
+
+#[test]
+fn test_formatting_keeps_tokens() {
+    for (path, source) in corpus() {
+        let formatted = format(source.clone(), &Config::default()).unwrap();
+        assert_eq!(
+            token_types(&source),
+            token_types(&formatted),
+            "{}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn test_formatted_script_is_unchanged() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/formatted.lox");
+    let source = fs::read_to_string(path).unwrap();
+    assert_eq!(source, format(source.clone(), &Config::default()).unwrap());
+}
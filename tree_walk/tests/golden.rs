@@ -0,0 +1,33 @@
+use std::process::Command;
+
+#[test]
+fn test_golden_scripts() {
+    let output = Command::new(env!("CARGO_BIN_EXE_tree_walk"))
+        .args(["test", "tests/golden"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn test_filter() {
+    let output = Command::new(env!("CARGO_BIN_EXE_tree_walk"))
+        .args([
+            "test",
+            "tests/golden",
+            "--filter",
+            "errors/",
+            "--filter",
+            "blocks",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(
+        "8 passed, 0 failed\n",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
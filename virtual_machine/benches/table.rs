@@ -0,0 +1,61 @@
+//! What a lookup costs in the VM's table, next to std's `HashMap` keyed by
+//! the string's characters, and what interning a string costs.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::collections::HashMap;
+use virtual_machine::table::Table;
+use virtual_machine::value::Strings;
+
+const SIZES: [usize; 3] = [16, 1024, 65536];
+
+fn lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup");
+    for size in SIZES {
+        let mut strings = Strings::new();
+        let keys: Vec<_> = (0..size)
+            .map(|n| strings.intern(&format!("name{}", n)))
+            .collect();
+        let missing = strings.intern("missing");
+
+        let mut table = Table::new();
+        let mut map = HashMap::new();
+        for (n, key) in keys.iter().enumerate() {
+            table.set(key.clone(), n);
+            map.insert(key.as_str().to_string(), n);
+        }
+
+        let key = &keys[size / 2];
+        group.bench_with_input(BenchmarkId::new("table hit", size), key, |b, key| {
+            b.iter(|| table.get(black_box(key)))
+        });
+        group.bench_with_input(BenchmarkId::new("table miss", size), &missing, |b, key| {
+            b.iter(|| table.get(black_box(key)))
+        });
+        group.bench_with_input(BenchmarkId::new("HashMap hit", size), key, |b, key| {
+            b.iter(|| map.get(black_box(key.as_str())))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("HashMap miss", size),
+            &missing,
+            |b, key| b.iter(|| map.get(black_box(key.as_str()))),
+        );
+    }
+    group.finish();
+}
+
+fn intern(c: &mut Criterion) {
+    let mut group = c.benchmark_group("intern");
+    for size in SIZES {
+        let mut strings = Strings::new();
+        for n in 0..size {
+            strings.intern(&format!("name{}", n));
+        }
+        group.bench_function(BenchmarkId::new("existing", size), |b| {
+            b.iter(|| strings.intern(black_box("name7")))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookup, intern);
+criterion_main!(benches);
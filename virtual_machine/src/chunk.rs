@@ -0,0 +1,578 @@
+use crate::value::{ObjString, Value};
+use std::collections::HashMap;
+
+/// How many constants a chunk can hold: `ConstantLong`'s index is 24 bits.
+pub const MAX_CONSTANTS: usize = 1 << 24;
+
+/// The first byte of every instruction. The numbering is the Python
+/// prototype's.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    Return = 1,
+    /// Followed by the value, a little-endian f32.
+    Immediate = 2,
+    Negate = 3,
+    Add = 4,
+    Subtract = 5,
+    Multiply = 6,
+    Divide = 7,
+    /// Followed by a one-byte index into the constant table.
+    Constant = 8,
+    /// Followed by a three-byte little-endian index into the constant table,
+    /// for chunks with more than 256 constants.
+    ConstantLong = 9,
+    Nil = 10,
+    Pop = 11,
+    /// Followed by a one-byte index of the name's constant; pops the value.
+    DefineGlobal = 12,
+    /// Followed by a one-byte index of the name's constant.
+    GetGlobal = 13,
+    /// Followed by a one-byte index of the name's constant; leaves the value
+    /// on the stack, as an assignment is an expression.
+    SetGlobal = 14,
+    /// Followed by a one-byte stack slot.
+    GetLocal = 15,
+    /// Followed by a one-byte stack slot; leaves the value on the stack.
+    SetLocal = 16,
+    /// `DefineGlobal` with a three-byte little-endian index, for names past
+    /// the first 256 constants.
+    DefineGlobalLong = 17,
+    /// `GetGlobal` with a three-byte little-endian index.
+    GetGlobalLong = 18,
+    /// `SetGlobal` with a three-byte little-endian index.
+    SetGlobalLong = 19,
+}
+
+impl OpCode {
+    /// How many bytes the constant index after the instruction takes, for
+    /// the instructions that have one.
+    pub fn index_width(self) -> usize {
+        match self {
+            OpCode::ConstantLong
+            | OpCode::DefineGlobalLong
+            | OpCode::GetGlobalLong
+            | OpCode::SetGlobalLong => 3,
+            _ => 1,
+        }
+    }
+
+    fn long(self) -> Option<OpCode> {
+        Some(match self {
+            OpCode::Constant => OpCode::ConstantLong,
+            OpCode::DefineGlobal => OpCode::DefineGlobalLong,
+            OpCode::GetGlobal => OpCode::GetGlobalLong,
+            OpCode::SetGlobal => OpCode::SetGlobalLong,
+            _ => return None,
+        })
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, u8> {
+        Ok(match byte {
+            1 => OpCode::Return,
+            2 => OpCode::Immediate,
+            3 => OpCode::Negate,
+            4 => OpCode::Add,
+            5 => OpCode::Subtract,
+            6 => OpCode::Multiply,
+            7 => OpCode::Divide,
+            8 => OpCode::Constant,
+            9 => OpCode::ConstantLong,
+            10 => OpCode::Nil,
+            11 => OpCode::Pop,
+            12 => OpCode::DefineGlobal,
+            13 => OpCode::GetGlobal,
+            14 => OpCode::SetGlobal,
+            15 => OpCode::GetLocal,
+            16 => OpCode::SetLocal,
+            17 => OpCode::DefineGlobalLong,
+            18 => OpCode::GetGlobalLong,
+            19 => OpCode::SetGlobalLong,
+            _ => return Err(byte),
+        })
+    }
+}
+
+/// A sequence of instructions, with the source line each byte came from and
+/// the constants they load.
+#[derive(Debug, Default, PartialEq)]
+pub struct Chunk {
+    code: Vec<u8>,
+    /// `(line, count)` runs: the first `count` bytes came from `line`, the
+    /// next run's from its line, and so on.
+    lines: Vec<(usize, usize)>,
+    constants: Vec<Value>,
+    /// Where each value already is in `constants`.
+    indexes: HashMap<Constant, usize>,
+}
+
+/// A constant as the table de-duplicates it: numbers by their bits, so that
+/// 0 and -0 stay apart and NaN is found again, and interned strings by
+/// address.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Constant {
+    Nil,
+    Number(u32),
+    String(*const ObjString),
+}
+
+impl From<&Value> for Constant {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Nil => Constant::Nil,
+            Value::Number(n) => Constant::Number(n.to_bits()),
+            Value::String(s) => Constant::String(std::rc::Rc::as_ptr(s)),
+        }
+    }
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A chunk as it was saved, constants at the indexes its code uses.
+    pub(crate) fn from_parts(
+        code: Vec<u8>,
+        lines: Vec<(usize, usize)>,
+        constants: Vec<Value>,
+    ) -> Self {
+        let mut indexes = HashMap::new();
+        for (index, value) in constants.iter().enumerate().rev() {
+            indexes.insert(value.into(), index);
+        }
+        Self {
+            code,
+            lines,
+            constants,
+            indexes,
+        }
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// The source line the byte at `offset` came from. Past the end of the
+    /// code it's the last byte's, 0 if there's no code.
+    pub fn get_line(&self, offset: usize) -> usize {
+        let mut start = 0;
+        for (line, count) in &self.lines {
+            start += count;
+            if offset < start {
+                return *line;
+            }
+        }
+        self.lines.last().map_or(0, |(line, _)| *line)
+    }
+
+    pub fn lines(&self) -> &[(usize, usize)] {
+        &self.lines
+    }
+
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    /// The `width`-byte little-endian operand at `offset`, if the code isn't
+    /// cut short.
+    pub fn operand(&self, offset: usize, width: usize) -> Option<usize> {
+        let bytes = self.code.get(offset..offset + width)?;
+        Some(
+            bytes
+                .iter()
+                .rev()
+                .fold(0, |n, byte| n << 8 | *byte as usize),
+        )
+    }
+
+    pub fn add_instruction(&mut self, opcode: OpCode, line: usize) {
+        self.write(opcode as u8, line);
+    }
+
+    /// The prototype's inline number, which only numbers have.
+    pub fn add_immediate(&mut self, n: f32, line: usize) {
+        self.add_instruction(OpCode::Immediate, line);
+        for byte in n.to_le_bytes() {
+            self.write(byte, line);
+        }
+    }
+
+    /// An instruction with a one-byte operand: a constant's index or a stack
+    /// slot.
+    pub fn add_byte_instruction(&mut self, opcode: OpCode, operand: u8, line: usize) {
+        self.add_instruction(opcode, line);
+        self.write(operand, line);
+    }
+
+    /// The value's index in the constant table, adding it if it isn't there
+    /// yet. None if the table is full.
+    pub fn make_constant(&mut self, value: Value) -> Option<usize> {
+        match self.indexes.get(&(&value).into()) {
+            Some(index) => Some(*index),
+            None if self.constants.len() < MAX_CONSTANTS => {
+                self.indexes.insert((&value).into(), self.constants.len());
+                self.constants.push(value);
+                Some(self.constants.len() - 1)
+            }
+            None => None,
+        }
+    }
+
+    /// Emits an instruction loading the value, reusing its slot in the
+    /// constant table if it's already there. None if the table is full.
+    pub fn add_constant(&mut self, value: Value, line: usize) -> Option<usize> {
+        let index = self.make_constant(value)?;
+        self.add_index_instruction(OpCode::Constant, index, line);
+        Some(index)
+    }
+
+    /// An instruction with a constant's index, or a stack slot: one byte if
+    /// it fits, otherwise the instruction's long form with three. Panics if
+    /// it doesn't fit and there's no long form.
+    pub fn add_index_instruction(&mut self, opcode: OpCode, index: usize, line: usize) {
+        match u8::try_from(index) {
+            Ok(byte) => self.add_byte_instruction(opcode, byte, line),
+            Err(_) => {
+                let long = opcode.long().expect("only constant indexes can be long");
+                self.add_instruction(long, line);
+                for byte in &index.to_le_bytes()[..3] {
+                    self.write(*byte, line);
+                }
+            }
+        }
+    }
+
+    /// A listing in the Python prototype's format: an instruction per line,
+    /// with its offset, its source line (`|` if the same as the previous
+    /// instruction's), its bytes in hex and its mnemonic.
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::new();
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let (instruction, next) = self.disassemble_instruction(offset);
+            listing.push_str(&instruction);
+            listing.push('\n');
+            offset = next;
+        }
+        listing
+    }
+
+    /// The listing line for the instruction at `offset`, and the offset of
+    /// the next one. There are no jumps or closures to list yet.
+    pub fn disassemble_instruction(&self, offset: usize) -> (String, usize) {
+        let line = self.get_line(offset);
+        let line = if offset > 0 && self.get_line(offset - 1) == line {
+            "|".to_string()
+        } else {
+            line.to_string()
+        };
+        let (mnemonic, next) = match OpCode::try_from(self.code[offset]) {
+            Ok(OpCode::Immediate) => match self.code.get(offset + 1..offset + 5) {
+                Some(bytes) => {
+                    let n = f32::from_le_bytes(bytes.try_into().unwrap());
+                    (format!("Immediate {:?}", n), offset + 5)
+                }
+                None => ("Immediate <truncated>".to_string(), self.code.len()),
+            },
+            Ok(
+                opcode @ (OpCode::Constant
+                | OpCode::ConstantLong
+                | OpCode::DefineGlobal
+                | OpCode::GetGlobal
+                | OpCode::SetGlobal
+                | OpCode::DefineGlobalLong
+                | OpCode::GetGlobalLong
+                | OpCode::SetGlobalLong),
+            ) => {
+                let width = opcode.index_width();
+                let mnemonic = match self.operand(offset + 1, width) {
+                    Some(index) => match self.constants.get(index) {
+                        Some(Value::String(s)) => {
+                            format!("{:?} {} '{}'", opcode, index, s.as_str())
+                        }
+                        Some(Value::Number(n)) => format!("{:?} {} '{:?}'", opcode, index, n),
+                        Some(value) => format!("{:?} {} '{}'", opcode, index, value),
+                        None => format!("{:?} {} <unknown>", opcode, index),
+                    },
+                    None => format!("{:?} <truncated>", opcode),
+                };
+                (mnemonic, (offset + 1 + width).min(self.code.len()))
+            }
+            Ok(opcode @ (OpCode::GetLocal | OpCode::SetLocal)) => match self.operand(offset + 1, 1)
+            {
+                Some(slot) => (format!("{:?} {}", opcode, slot), offset + 2),
+                None => (format!("{:?} <truncated>", opcode), self.code.len()),
+            },
+            Ok(opcode) => (format!("{:?}", opcode), offset + 1),
+            Err(byte) => (format!("Unknown {}", byte), offset + 1),
+        };
+        let bytes: Vec<String> = self.code[offset..next]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let listing = format!("{} {:>4} 0x{} {}", offset, line, bytes.join(" "), mnemonic);
+        (listing, next)
+    }
+
+    fn write(&mut self, byte: u8, line: usize) {
+        self.code.push(byte);
+        match self.lines.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => self.lines.push((line, 1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Strings;
+
+    #[test]
+    fn test_opcodes() {
+        for opcode in [
+            OpCode::Return,
+            OpCode::Immediate,
+            OpCode::Negate,
+            OpCode::Add,
+            OpCode::Subtract,
+            OpCode::Multiply,
+            OpCode::Divide,
+            OpCode::Constant,
+            OpCode::ConstantLong,
+            OpCode::Nil,
+            OpCode::Pop,
+            OpCode::DefineGlobal,
+            OpCode::GetGlobal,
+            OpCode::SetGlobal,
+            OpCode::GetLocal,
+            OpCode::SetLocal,
+            OpCode::DefineGlobalLong,
+            OpCode::GetGlobalLong,
+            OpCode::SetGlobalLong,
+        ] {
+            assert_eq!(Ok(opcode), OpCode::try_from(opcode as u8));
+        }
+        assert_eq!(Err(0), OpCode::try_from(0));
+        assert_eq!(Err(20), OpCode::try_from(20));
+    }
+
+    #[test]
+    fn test_immediate() {
+        let mut chunk = Chunk::new();
+        chunk.add_immediate(5.0, 3);
+        let mut expected = vec![2];
+        expected.extend(5.0f32.to_le_bytes());
+        assert_eq!(expected, chunk.code());
+        assert_eq!(3, chunk.get_line(4));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        // the prototype's ByteCodeTests.test_disassemble
+        let mut chunk = Chunk::new();
+        chunk.add_immediate(5.0, 1);
+        chunk.add_instruction(OpCode::Return, 1);
+        chunk.add_instruction(OpCode::Return, 2);
+        assert_eq!(
+            "0    1 0x02 00 00 a0 40 Immediate 5.0\n\
+             5    | 0x01 Return\n\
+             6    2 0x01 Return\n",
+            chunk.disassemble()
+        );
+    }
+
+    #[test]
+    fn test_disassemble_every_opcode() {
+        let mut chunk = Chunk::new();
+        chunk.add_immediate(-0.5, 10);
+        for opcode in [
+            OpCode::Negate,
+            OpCode::Add,
+            OpCode::Subtract,
+            OpCode::Multiply,
+            OpCode::Divide,
+            OpCode::Return,
+        ] {
+            chunk.add_instruction(opcode, 10);
+        }
+        assert_eq!(
+            "0   10 0x02 00 00 00 bf Immediate -0.5\n\
+             5    | 0x03 Negate\n\
+             6    | 0x04 Add\n\
+             7    | 0x05 Subtract\n\
+             8    | 0x06 Multiply\n\
+             9    | 0x07 Divide\n\
+             10    | 0x01 Return\n",
+            chunk.disassemble()
+        );
+    }
+
+    #[test]
+    fn test_disassemble_malformed() {
+        let mut chunk = Chunk::new();
+        chunk.write(0xff, 0);
+        chunk.add_instruction(OpCode::Immediate, 0);
+        chunk.write(0, 0);
+        assert_eq!(
+            "0    0 0xff Unknown 255\n\
+             1    | 0x02 00 Immediate <truncated>\n",
+            chunk.disassemble()
+        );
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut chunk = Chunk::new();
+        assert_eq!(Some(0), chunk.add_constant(Value::Number(1.5), 1));
+        assert_eq!(Some(1), chunk.add_constant(Value::Number(2.0), 1));
+        assert_eq!(Some(0), chunk.add_constant(Value::Number(1.5), 2));
+        assert_eq!(&[8, 0, 8, 1, 8, 0], chunk.code());
+        assert_eq!(&[Value::Number(1.5), Value::Number(2.0)], chunk.constants());
+        assert_eq!(2, chunk.get_line(5));
+    }
+
+    #[test]
+    fn test_constants_by_bits() {
+        let mut chunk = Chunk::new();
+        for n in [0.0, -0.0, f32::NAN, f32::NAN, 0.0] {
+            chunk.add_constant(Value::Number(n), 0);
+        }
+        assert_eq!(&[8, 0, 8, 1, 8, 2, 8, 2, 8, 0], chunk.code());
+        assert_eq!(3, chunk.constants().len());
+    }
+
+    #[test]
+    fn test_string_constants() {
+        let mut strings = Strings::new();
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::String(strings.intern("a")), 0);
+        chunk.add_constant(Value::String(strings.intern("b")), 0);
+        chunk.add_constant(Value::String(strings.intern("a")), 0);
+        chunk.add_constant(Value::Number(1.0), 0);
+        assert_eq!(&[8, 0, 8, 1, 8, 0, 8, 2], chunk.code());
+        assert_eq!(
+            "0    0 0x08 00 Constant 0 'a'\n\
+             2    | 0x08 01 Constant 1 'b'\n\
+             4    | 0x08 00 Constant 0 'a'\n\
+             6    | 0x08 02 Constant 2 '1.0'\n",
+            chunk.disassemble()
+        );
+    }
+
+    #[test]
+    fn test_long_constants() {
+        let mut chunk = Chunk::new();
+        for n in 0..300 {
+            chunk.add_constant(Value::Number(n as f32), 0);
+        }
+        assert_eq!(&[8, 255], &chunk.code()[510..512]);
+        assert_eq!(&[9, 0, 1, 0], &chunk.code()[512..516]);
+        assert_eq!(Some(299), chunk.operand(chunk.code().len() - 3, 3));
+        // reused constants keep their short form
+        chunk.add_constant(Value::Number(7.0), 0);
+        assert_eq!(&[8, 7], &chunk.code()[chunk.code().len() - 2..]);
+    }
+
+    #[test]
+    fn test_disassemble_constants() {
+        let mut chunk = Chunk::new();
+        for n in 0..257 {
+            chunk.add_constant(Value::Number(n as f32), 3);
+        }
+        let listing = chunk.disassemble();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!("0    3 0x08 00 Constant 0 '0.0'", lines[0]);
+        assert_eq!("2    | 0x08 01 Constant 1 '1.0'", lines[1]);
+        assert_eq!(
+            "512    | 0x09 00 01 00 ConstantLong 256 '256.0'",
+            lines[256]
+        );
+
+        let mut chunk = Chunk::new();
+        chunk.add_instruction(OpCode::Constant, 0);
+        chunk.write(4, 0);
+        chunk.add_instruction(OpCode::ConstantLong, 0);
+        chunk.write(0, 0);
+        assert_eq!(
+            "0    0 0x08 04 Constant 4 <unknown>\n\
+             2    | 0x09 00 ConstantLong <truncated>\n",
+            chunk.disassemble()
+        );
+    }
+
+    #[test]
+    fn test_disassemble_variables() {
+        let mut strings = Strings::new();
+        let mut chunk = Chunk::new();
+        let name = chunk.make_constant(Value::String(strings.intern("a")));
+        assert_eq!(Some(0), name);
+        assert!(chunk.code().is_empty());
+        chunk.add_instruction(OpCode::Nil, 0);
+        chunk.add_byte_instruction(OpCode::DefineGlobal, 0, 0);
+        chunk.add_byte_instruction(OpCode::GetGlobal, 0, 1);
+        chunk.add_byte_instruction(OpCode::SetGlobal, 0, 1);
+        chunk.add_byte_instruction(OpCode::GetLocal, 1, 2);
+        chunk.add_byte_instruction(OpCode::SetLocal, 0, 2);
+        chunk.add_instruction(OpCode::Pop, 2);
+        chunk.add_instruction(OpCode::GetLocal, 2);
+        assert_eq!(
+            "0    0 0x0a Nil\n\
+             1    | 0x0c 00 DefineGlobal 0 'a'\n\
+             3    1 0x0d 00 GetGlobal 0 'a'\n\
+             5    | 0x0e 00 SetGlobal 0 'a'\n\
+             7    2 0x0f 01 GetLocal 1\n\
+             9    | 0x10 00 SetLocal 0\n\
+             11    | 0x0b Pop\n\
+             12    | 0x0f GetLocal <truncated>\n",
+            chunk.disassemble()
+        );
+
+        let mut chunk = Chunk::new();
+        for n in 0..256 {
+            chunk.make_constant(Value::Number(n as f32));
+        }
+        chunk.add_instruction(OpCode::Nil, 0);
+        let name = chunk.make_constant(Value::String(strings.intern("b")));
+        chunk.add_index_instruction(OpCode::DefineGlobal, name.unwrap(), 0);
+        chunk.add_index_instruction(OpCode::GetGlobal, 0, 0);
+        assert_eq!(
+            "0    0 0x0a Nil\n\
+             1    | 0x11 00 01 00 DefineGlobalLong 256 'b'\n\
+             5    | 0x0d 00 GetGlobal 0 '0.0'\n",
+            chunk.disassemble()
+        );
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut chunk = Chunk::new();
+        chunk.add_instruction(OpCode::Negate, 1);
+        chunk.add_instruction(OpCode::Return, 2);
+        assert_eq!(&[3, 1], chunk.code());
+        assert_eq!(1, chunk.get_line(0));
+        assert_eq!(2, chunk.get_line(1));
+        assert_eq!(2, chunk.get_line(2));
+        assert_eq!(0, Chunk::new().get_line(0));
+    }
+
+    #[test]
+    fn test_lines_are_run_length_encoded() {
+        let mut chunk = Chunk::new();
+        for _ in 0..100 {
+            chunk.add_instruction(OpCode::Negate, 7);
+        }
+        chunk.add_constant(Value::Number(1.0), 8);
+        chunk.add_instruction(OpCode::Return, 7);
+        assert_eq!(&[(7, 100), (8, 2), (7, 1)], chunk.lines());
+        assert_eq!(7, chunk.get_line(99));
+        assert_eq!(8, chunk.get_line(100));
+        assert_eq!(8, chunk.get_line(101));
+        assert_eq!(7, chunk.get_line(102));
+    }
+}
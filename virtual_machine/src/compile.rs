@@ -0,0 +1,550 @@
+//! Compiles programs parsed by tree_walk into chunks, so both backends share
+//! one frontend.
+
+use crate::chunk::{Chunk, OpCode};
+use crate::value::{Strings, Value};
+use std::collections::VecDeque;
+use tree_walk::expression::{self, Expression};
+use tree_walk::parse::{self, parse_repl};
+use tree_walk::resolve;
+use tree_walk::scan::{self, scan_tokens, Token, TokenType};
+use tree_walk::statement::Statement;
+
+/// How many locals can be in scope at once: `GetLocal`'s slot is one byte.
+pub const MAX_LOCALS: usize = 256;
+
+/// Why source didn't compile, from either compiler.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Scan(scan::Error),
+    Parse {
+        error: parse::Error,
+        line: usize,
+    },
+    /// A local misused, caught as it's resolved to a slot.
+    Resolve(resolve::Error),
+    /// Lox the tree-walker runs but this VM has no instructions for yet.
+    Unsupported {
+        what: String,
+        line: usize,
+    },
+    TooManyConstants {
+        line: usize,
+    },
+    TooManyLocals {
+        line: usize,
+    },
+}
+
+impl Error {
+    pub fn line(&self) -> usize {
+        match self {
+            Error::Scan(error) => error.line(),
+            Error::Resolve(error) => error.line(),
+            Error::Parse { line, .. }
+            | Error::Unsupported { line, .. }
+            | Error::TooManyConstants { line }
+            | Error::TooManyLocals { line } => *line,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Scan(error) => write!(f, "{}", error),
+            Error::Parse { error, .. } => write!(f, "{}", error),
+            Error::Resolve(error) => write!(f, "{}", error),
+            Error::Unsupported { what, .. } => write!(f, "Can't compile {} yet.", what),
+            Error::TooManyConstants { .. } => write!(f, "Too many constants in one chunk."),
+            Error::TooManyLocals { .. } => write!(f, "Too many local variables in function."),
+        }
+    }
+}
+
+/// The locals in scope, innermost last. Locals are pushed as they're
+/// declared and popped as their block ends, so a local's index here is its
+/// slot on the VM's stack.
+#[derive(Default)]
+pub(crate) struct Locals {
+    /// Each local's name and the depth of its block, None while its
+    /// initializer is being compiled.
+    locals: Vec<(String, Option<usize>)>,
+    depth: usize,
+}
+
+impl Locals {
+    /// Outside every block, `var` declares a global.
+    pub(crate) fn in_block(&self) -> bool {
+        self.depth > 0
+    }
+
+    pub(crate) fn begin_block(&mut self) {
+        self.depth += 1;
+    }
+
+    /// Forgets the innermost block's locals, returning how many to pop.
+    pub(crate) fn end_block(&mut self) -> usize {
+        self.depth -= 1;
+        let mut count = 0;
+        while let Some((_, depth)) = self.locals.last() {
+            if depth.is_some_and(|depth| depth <= self.depth) {
+                break;
+            }
+            self.locals.pop();
+            count += 1;
+        }
+        count
+    }
+
+    /// Adds a local to the innermost block, unreadable until it's defined.
+    pub(crate) fn declare(&mut self, name: &Token) -> Result<(), Error> {
+        let text = name.to_string();
+        let already = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|(_, depth)| depth.is_none_or(|depth| depth == self.depth))
+            .any(|(other, _)| *other == text);
+        if already {
+            return Err(Error::Resolve(resolve::Error::AlreadyDeclared {
+                name: text,
+                line: name.line(),
+            }));
+        }
+        if self.locals.len() == MAX_LOCALS {
+            return Err(Error::TooManyLocals { line: name.line() });
+        }
+        self.locals.push((text, None));
+        Ok(())
+    }
+
+    /// Makes the last local declared readable, once its initializer is done.
+    pub(crate) fn define(&mut self) {
+        if let Some((_, depth)) = self.locals.last_mut() {
+            *depth = Some(self.depth);
+        }
+    }
+
+    /// The slot of the innermost local with the name, or None if the name
+    /// is a global's.
+    pub(crate) fn resolve(&self, name: &Token) -> Result<Option<u8>, Error> {
+        let text = name.to_string();
+        match self.locals.iter().rposition(|(other, _)| *other == text) {
+            Some(slot) if self.locals[slot].1.is_none() => {
+                Err(Error::Resolve(resolve::Error::ReadInOwnInitializer {
+                    name: text,
+                    line: name.line(),
+                }))
+            }
+            Some(slot) => Ok(Some(slot as u8)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The instructions that read and assign the variable, and their operand: a
+/// local's slot, or the index of a global's name in the constant table.
+pub(crate) fn variable(
+    chunk: &mut Chunk,
+    strings: &mut Strings,
+    locals: &Locals,
+    name: &Token,
+) -> Result<(OpCode, OpCode, usize), Error> {
+    Ok(match locals.resolve(name)? {
+        Some(slot) => (OpCode::GetLocal, OpCode::SetLocal, slot as usize),
+        None => (
+            OpCode::GetGlobal,
+            OpCode::SetGlobal,
+            name_constant(chunk, strings, name)?,
+        ),
+    })
+}
+
+/// The index of the global's name in the constant table. Past the first
+/// 256, the global instructions take their long forms.
+pub(crate) fn name_constant(
+    chunk: &mut Chunk,
+    strings: &mut Strings,
+    name: &Token,
+) -> Result<usize, Error> {
+    let string = strings.intern(&name.to_string());
+    chunk
+        .make_constant(Value::String(string))
+        .ok_or(Error::TooManyConstants { line: name.line() })
+}
+
+/// The line for an instruction that no token asks for, such as the `Pop`
+/// after an expression statement: the line of the instruction before it.
+pub(crate) fn last_line(chunk: &Chunk) -> usize {
+    chunk
+        .code()
+        .len()
+        .checked_sub(1)
+        .map_or(0, |offset| chunk.get_line(offset))
+}
+
+/// Scans and parses a program with tree_walk, then compiles the tree. As at
+/// tree_walk's prompt, the last statement may be an expression with no
+/// semicolon, whose value the chunk returns.
+pub fn compile_source(source: &str, strings: &mut Strings) -> Result<Chunk, Error> {
+    let tokens = scan_tokens(source.to_string()).map_err(Error::Scan)?;
+    let (statements, result) =
+        parse_repl(VecDeque::from(tokens)).map_err(|(error, line)| Error::Parse { error, line })?;
+    compile(&statements, result.as_deref(), strings)
+}
+
+/// A chunk that runs the statements, then evaluates `result` and returns its
+/// value. Its strings are interned in `strings`, which should be the VM's
+/// that will run it.
+pub fn compile(
+    statements: &[Statement],
+    result: Option<&Expression>,
+    strings: &mut Strings,
+) -> Result<Chunk, Error> {
+    let mut compiler = Compiler {
+        chunk: Chunk::new(),
+        strings,
+        locals: Locals::default(),
+        line: 0,
+    };
+    for statement in statements {
+        compiler.statement(statement)?;
+    }
+    if let Some(result) = result {
+        compiler.expression(result)?;
+    }
+    let line = last_line(&compiler.chunk);
+    compiler.chunk.add_instruction(OpCode::Return, line);
+    Ok(compiler.chunk)
+}
+
+struct Compiler<'a> {
+    chunk: Chunk,
+    strings: &'a mut Strings,
+    locals: Locals,
+    // The line of the last token compiled, for errors about what comes next.
+    line: usize,
+}
+
+impl Compiler<'_> {
+    fn statement(&mut self, statement: &Statement) -> Result<(), Error> {
+        match statement {
+            Statement::Expression { expression } => {
+                self.expression(expression)?;
+                self.chunk
+                    .add_instruction(OpCode::Pop, last_line(&self.chunk));
+            }
+            Statement::Var { name, initializer } => {
+                self.line = name.line();
+                // the name goes in the constant table before the initializer's
+                // constants, as in the single-pass compiler
+                let global = if self.locals.in_block() {
+                    self.locals.declare(name)?;
+                    None
+                } else {
+                    Some(name_constant(&mut self.chunk, self.strings, name)?)
+                };
+                match initializer {
+                    Some(initializer) => self.expression(initializer)?,
+                    None => self.chunk.add_instruction(OpCode::Nil, name.line()),
+                }
+                match global {
+                    Some(index) => {
+                        self.chunk
+                            .add_index_instruction(OpCode::DefineGlobal, index, name.line())
+                    }
+                    None => self.locals.define(),
+                }
+            }
+            Statement::Block { statements } => {
+                self.locals.begin_block();
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                for _ in 0..self.locals.end_block() {
+                    self.chunk
+                        .add_instruction(OpCode::Pop, last_line(&self.chunk));
+                }
+            }
+            Statement::Print { .. } => return Err(self.unsupported("print statements".to_string())),
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<(), Error> {
+        match expression {
+            Expression::Literal { value, line } => {
+                self.line = *line;
+                match value {
+                    expression::Value::Float(n) => self.constant(Value::Number(*n))?,
+                    expression::Value::Str(s) => {
+                        let string = self.strings.intern(s);
+                        self.constant(Value::String(string))?
+                    }
+                    expression::Value::Nil => self.chunk.add_instruction(OpCode::Nil, *line),
+                    value => return Err(self.unsupported(format!("'{}'", value))),
+                }
+            }
+            Expression::Grouping { expression } => self.expression(expression)?,
+            Expression::Unary {
+                operator,
+                expression,
+            } => {
+                self.line = operator.line();
+                let opcode = match operator.token_type() {
+                    TokenType::Minus => OpCode::Negate,
+                    _ => return Err(self.unsupported(format!("'{}'", operator))),
+                };
+                self.expression(expression)?;
+                self.chunk.add_instruction(opcode, operator.line());
+            }
+            Expression::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.line = operator.line();
+                let opcode =
+                    binary(operator).ok_or_else(|| self.unsupported(format!("'{}'", operator)))?;
+                self.expression(left)?;
+                self.line = operator.line();
+                self.expression(right)?;
+                self.chunk.add_instruction(opcode, operator.line());
+            }
+            Expression::Variable { name } => {
+                self.line = name.line();
+                let (get, _, operand) =
+                    variable(&mut self.chunk, self.strings, &self.locals, name)?;
+                self.chunk.add_index_instruction(get, operand, name.line());
+            }
+            Expression::Assign { name, value } => {
+                self.line = name.line();
+                let (_, set, operand) =
+                    variable(&mut self.chunk, self.strings, &self.locals, name)?;
+                self.expression(value)?;
+                self.chunk.add_index_instruction(set, operand, name.line());
+            }
+            Expression::Call { paren, .. } => {
+                self.line = paren.line();
+                return Err(self.unsupported("calls".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn constant(&mut self, value: Value) -> Result<(), Error> {
+        match self.chunk.add_constant(value, self.line) {
+            Some(_) => Ok(()),
+            None => Err(Error::TooManyConstants { line: self.line }),
+        }
+    }
+
+    fn unsupported(&self, what: String) -> Error {
+        Error::Unsupported {
+            what,
+            line: self.line,
+        }
+    }
+}
+
+fn binary(operator: &Token) -> Option<OpCode> {
+    Some(match operator.token_type() {
+        TokenType::Plus => OpCode::Add,
+        TokenType::Minus => OpCode::Subtract,
+        TokenType::Star => OpCode::Multiply,
+        TokenType::Slash => OpCode::Divide,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{InterpretResult, VirtualMachine};
+
+    fn compiled(source: &str) -> Result<Chunk, Error> {
+        compile_source(source, &mut Strings::new())
+    }
+
+    #[test]
+    fn test_compile() {
+        let mut expected = Chunk::new();
+        expected.add_constant(Value::Number(1.0), 0);
+        expected.add_constant(Value::Number(2.0), 0);
+        expected.add_constant(Value::Number(3.0), 0);
+        expected.add_instruction(OpCode::Negate, 0);
+        expected.add_instruction(OpCode::Multiply, 0);
+        expected.add_instruction(OpCode::Add, 0);
+        expected.add_instruction(OpCode::Return, 0);
+        assert_eq!(Ok(expected), compiled("1 + 2 * -3"));
+    }
+
+    #[test]
+    fn test_lines() {
+        let chunk = compiled("(1 -\n2)\n/\n4").unwrap();
+        // each constant on its own line, each operator on the line of its
+        // token, and Return on the line of the instruction before it
+        let lines: Vec<usize> = (0..chunk.code().len()).map(|i| chunk.get_line(i)).collect();
+        assert_eq!(vec![0, 0, 1, 1, 0, 3, 3, 2, 2], lines);
+    }
+
+    #[test]
+    fn test_runs() {
+        let chunk = compiled("1 + 2 * -3").unwrap();
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(-5.0))),
+            VirtualMachine::new().execute(&chunk)
+        );
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(
+            Err(Error::Unsupported {
+                what: "'!'".to_string(),
+                line: 0
+            }),
+            compiled("!1")
+        );
+        assert_eq!(
+            "Can't compile calls yet.",
+            compiled("1 +\na()").unwrap_err().to_string()
+        );
+        assert_eq!(1, compiled("1 +\na()").unwrap_err().line());
+        assert_eq!(
+            "Can't compile '<' yet.",
+            compiled("1 < 2").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Can't compile 'true' yet.",
+            compiled("true").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Can't compile print statements yet.",
+            compiled("print 1;").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_globals() {
+        let chunk = compiled("var a = 1;\nvar b;\na = b = a + 2;\na").unwrap();
+        // the names are constants 0 and 2, with 1 in between
+        assert_eq!(
+            &[
+                8, 1, 12, 0, //
+                10, 12, 2, //
+                13, 0, 8, 3, 4, 14, 2, 14, 0, 11, //
+                13, 0, 1,
+            ],
+            chunk.code()
+        );
+        assert_eq!(Value::Number(2.0), chunk.constants()[3]);
+        // a global's instructions are on its name's line
+        assert_eq!(3, chunk.get_line(chunk.code().len() - 3));
+    }
+
+    #[test]
+    fn test_locals() {
+        let chunk = compiled("{ var a = 1; { var b = a; b = a = 2; } var c; c; }").unwrap();
+        assert_eq!(
+            &[
+                8, 0, //
+                15, 0, //
+                8, 1, 16, 0, 16, 1, 11, //
+                11, //
+                10, //
+                15, 1, 11, //
+                11, 11, //
+                1,
+            ],
+            chunk.code()
+        );
+        // and no names, since locals are only slots
+        assert_eq!(2, chunk.constants().len());
+
+        // shadowing in an inner block, and reading the outer one after
+        let chunk = compiled("{ var a = 1; { var b = a; var a = 2; a; } a; }").unwrap();
+        assert_eq!(
+            &[8, 0, 15, 0, 8, 1, 15, 2, 11, 11, 11, 15, 0, 11, 11, 1],
+            chunk.code()
+        );
+    }
+
+    #[test]
+    fn test_local_errors() {
+        assert_eq!(
+            Err(Error::Resolve(resolve::Error::AlreadyDeclared {
+                name: "a".to_string(),
+                line: 1
+            })),
+            compiled("{ var a;\nvar a; }")
+        );
+        assert_eq!(
+            Err(Error::Resolve(resolve::Error::ReadInOwnInitializer {
+                name: "a".to_string(),
+                line: 0
+            })),
+            compiled("{ var a = 1; { var a = a; } }")
+        );
+        // globals can be declared again, and read before they're defined
+        assert!(compiled("var a; var a = a;").is_ok());
+        assert_eq!(
+            "Can't read local variable 'a' in its own initializer.",
+            compiled("{ var a = a; }").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_too_many_locals() {
+        let declarations: Vec<String> = (0..MAX_LOCALS).map(|n| format!("var a{};", n)).collect();
+        let source = format!("{{ {} }}", declarations.join("\n"));
+        let chunk = compiled(&source).unwrap();
+        assert_eq!(
+            MAX_LOCALS,
+            chunk.code().iter().filter(|b| **b == 11).count()
+        );
+
+        let source = format!("{{ {}\nvar b; }}", declarations.join(" "));
+        assert_eq!(Err(Error::TooManyLocals { line: 1 }), compiled(&source));
+        assert_eq!(
+            "Too many local variables in function.",
+            compiled(&source).unwrap_err().to_string()
+        );
+        // a block's locals free their slots when it ends
+        let source = format!("{{ {} }} {{ var b; }}", declarations.join(" "));
+        assert!(compiled(&source).is_ok());
+    }
+
+    #[test]
+    fn test_long_global_names() {
+        let declarations: Vec<String> = (0..256).map(|n| format!("var a{};", n)).collect();
+        let source = format!("{}\nvar b = 1; b = b + 1; b", declarations.join(" "));
+        let chunk = compiled(&source).unwrap();
+        // b's name is the 257th constant, so it takes the long forms
+        let long: Vec<u8> = vec![17, 18, 19]
+            .into_iter()
+            .filter(|opcode| chunk.code().contains(opcode))
+            .collect();
+        assert_eq!(vec![17, 18, 19], long);
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(2.0))),
+            VirtualMachine::new().execute(&chunk)
+        );
+    }
+
+    #[test]
+    fn test_frontend_errors() {
+        assert_eq!(
+            Err(Error::Parse {
+                error: parse::Error::NoExpression,
+                line: 1
+            }),
+            compiled("1 +\n")
+        );
+        assert_eq!(
+            "Unexpected character '#'.",
+            compiled("#").unwrap_err().to_string()
+        );
+    }
+}
@@ -0,0 +1,354 @@
+//! Compiled chunks on disk, in `.loxc` files. Everything is little-endian:
+//!
+//! ```text
+//! magic      b"LOXC"
+//! version    u16
+//! constants  u32 count, then per constant a u8 tag and its value:
+//!              0  number, an f32
+//!              1  string, a u32 length then that many bytes of UTF-8
+//!              2  nil, with no value
+//! code       u32 length, then the bytes
+//! lines      u32 count, then (u32 line, u32 bytes) runs covering the code
+//! functions  u32 count, reserved: always 0 for now
+//! ```
+//!
+//! The function count is reserved for when the VM has functions, whose
+//! chunks will follow in the same layout, so that adding them won't need a
+//! new version. Until then it's written as 0 and files with functions are
+//! rejected.
+
+use crate::chunk::{Chunk, MAX_CONSTANTS};
+use crate::value::{Strings, Value};
+use std::io::{self, Read, Write};
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const VERSION: u16 = 1;
+
+const NUMBER: u8 = 0;
+const STRING: u8 = 1;
+const NIL: u8 = 2;
+
+/// Why a file didn't load.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// Not a `.loxc` file at all.
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// The file ends partway through.
+    Truncated,
+    UnknownConstantTag(u8),
+    InvalidString,
+    TooManyConstants(usize),
+    /// The line runs don't add up to the code's length.
+    LinesMismatch {
+        lines: usize,
+        code: usize,
+    },
+    FunctionsUnsupported,
+    /// More bytes after the chunk.
+    TrailingBytes,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::BadMagic => write!(f, "Not a compiled Lox file."),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "Unsupported format version {}.", version)
+            }
+            Error::Truncated => write!(f, "Unexpected end of file."),
+            Error::UnknownConstantTag(tag) => write!(f, "Unknown constant type {}.", tag),
+            Error::InvalidString => write!(f, "String constant isn't UTF-8."),
+            Error::TooManyConstants(count) => write!(f, "Too many constants ({}).", count),
+            Error::LinesMismatch { lines, code } => write!(
+                f,
+                "Line table covers {} bytes but the code has {}.",
+                lines, code
+            ),
+            Error::FunctionsUnsupported => write!(f, "Functions aren't supported yet."),
+            Error::TrailingBytes => write!(f, "Unexpected data after the chunk."),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Truncated,
+            _ => Error::Io(error),
+        }
+    }
+}
+
+impl Chunk {
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        write_u32(writer, self.constants().len())?;
+        for constant in self.constants() {
+            match constant {
+                Value::Number(n) => {
+                    writer.write_all(&[NUMBER])?;
+                    writer.write_all(&n.to_le_bytes())?;
+                }
+                Value::String(s) => {
+                    writer.write_all(&[STRING])?;
+                    write_u32(writer, s.as_str().len())?;
+                    writer.write_all(s.as_str().as_bytes())?;
+                }
+                Value::Nil => writer.write_all(&[NIL])?,
+            }
+        }
+
+        write_u32(writer, self.code().len())?;
+        writer.write_all(self.code())?;
+
+        write_u32(writer, self.lines().len())?;
+        for (line, count) in self.lines() {
+            write_u32(writer, *line)?;
+            write_u32(writer, *count)?;
+        }
+
+        write_u32(writer, 0)
+    }
+
+    /// Reads a chunk written by `write_to`, checking that it's all there and
+    /// that its sizes agree. Whether its code makes sense is left to the
+    /// verifier. Its strings are interned in `strings`, as a compiler would.
+    pub fn read_from(reader: &mut impl Read, strings: &mut Strings) -> Result<Chunk, Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let count = read_u32(reader)?;
+        if count > MAX_CONSTANTS {
+            return Err(Error::TooManyConstants(count));
+        }
+        let mut constants = Vec::new();
+        for _ in 0..count {
+            let mut tag = [0];
+            reader.read_exact(&mut tag)?;
+            match tag[0] {
+                NUMBER => {
+                    let mut n = [0; 4];
+                    reader.read_exact(&mut n)?;
+                    constants.push(Value::Number(f32::from_le_bytes(n)));
+                }
+                STRING => {
+                    let bytes = read_bytes(reader)?;
+                    let chars = std::str::from_utf8(&bytes).map_err(|_| Error::InvalidString)?;
+                    constants.push(Value::String(strings.intern(chars)));
+                }
+                NIL => constants.push(Value::Nil),
+                tag => return Err(Error::UnknownConstantTag(tag)),
+            }
+        }
+
+        let code = read_bytes(reader)?;
+
+        let runs = read_u32(reader)?;
+        let mut lines = Vec::new();
+        let mut covered = 0;
+        for _ in 0..runs {
+            let line = read_u32(reader)?;
+            let count = read_u32(reader)?;
+            covered += count;
+            if covered > code.len() {
+                return Err(Error::LinesMismatch {
+                    lines: covered,
+                    code: code.len(),
+                });
+            }
+            lines.push((line, count));
+        }
+        if covered != code.len() {
+            return Err(Error::LinesMismatch {
+                lines: covered,
+                code: code.len(),
+            });
+        }
+
+        if read_u32(reader)? != 0 {
+            return Err(Error::FunctionsUnsupported);
+        }
+        if reader.read(&mut [0])? != 0 {
+            return Err(Error::TrailingBytes);
+        }
+        Ok(Chunk::from_parts(code, lines, constants))
+    }
+}
+
+fn write_u32(writer: &mut impl Write, n: usize) -> io::Result<()> {
+    let n =
+        u32::try_from(n).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "too large"))?;
+    writer.write_all(&n.to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<usize> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes) as usize)
+}
+
+/// A u32 length and that many bytes, read in pieces so that a corrupt length
+/// can't allocate more than the file holds.
+fn read_bytes(reader: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let length = read_u32(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != length {
+        return Err(Error::Truncated);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::OpCode;
+    use crate::pratt;
+    use crate::value::Strings;
+
+    fn compiled(source: &str) -> Result<Chunk, crate::compile::Error> {
+        pratt::compile(source, &mut Strings::new())
+    }
+
+    fn saved(chunk: &Chunk) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        chunk.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn loaded(bytes: &[u8]) -> Result<Chunk, Error> {
+        Chunk::read_from(&mut &bytes[..], &mut Strings::new())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = compiled("1 +\n2 *\n\n-1.5 / 1").unwrap();
+        assert_eq!(chunk, loaded(&saved(&chunk)).unwrap());
+        assert_eq!(Chunk::new(), loaded(&saved(&Chunk::new())).unwrap());
+
+        let mut nil = Chunk::new();
+        nil.add_constant(Value::Nil, 0);
+        assert_eq!(nil, loaded(&saved(&nil)).unwrap());
+    }
+
+    #[test]
+    fn test_strings_round_trip() {
+        let mut strings = Strings::new();
+        let chunk = pratt::compile("\"h\" + \"é\" + \"h\"", &mut strings).unwrap();
+        let copy = Chunk::read_from(&mut &saved(&chunk)[..], &mut strings).unwrap();
+        // the same objects, having been interned in the same table
+        assert_eq!(chunk, copy);
+
+        let mut bytes = saved(&chunk);
+        let e = bytes.iter().position(|b| *b == 0xc3).unwrap();
+        bytes[e] = 0xff;
+        assert!(matches!(loaded(&bytes), Err(Error::InvalidString)));
+    }
+
+    #[test]
+    fn test_layout() {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Number(2.0), 3);
+        chunk.add_instruction(OpCode::Return, 3);
+        chunk.add_instruction(OpCode::Return, 4);
+        let mut expected = b"LOXC".to_vec();
+        expected.extend([1, 0]);
+        expected.extend([1, 0, 0, 0, 0, 0, 0, 0, 0x40]);
+        expected.extend([4, 0, 0, 0, 8, 0, 1, 1]);
+        expected.extend([2, 0, 0, 0, 3, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0]);
+        expected.extend([0, 0, 0, 0]);
+        assert_eq!(expected, saved(&chunk));
+    }
+
+    #[test]
+    fn test_duplicate_constants_keep_their_indexes() {
+        let mut bytes = saved(&Chunk::new());
+        bytes[6] = 2;
+        bytes.splice(10..10, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let chunk = loaded(&bytes).unwrap();
+        assert_eq!(&[Value::Number(0.0), Value::Number(0.0)], chunk.constants());
+    }
+
+    #[test]
+    fn test_every_truncation_fails() {
+        let bytes = saved(&compiled("1 + 2").unwrap());
+        for length in 0..bytes.len() {
+            assert!(
+                matches!(loaded(&bytes[..length]), Err(Error::Truncated)),
+                "{}",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let bytes = saved(&compiled("1 + 2").unwrap());
+
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert!(matches!(loaded(&bad), Err(Error::BadMagic)));
+
+        let mut bad = bytes.clone();
+        bad[4] = 2;
+        assert!(matches!(loaded(&bad), Err(Error::UnsupportedVersion(2))));
+
+        let mut bad = bytes.clone();
+        bad[10] = 7;
+        assert!(matches!(loaded(&bad), Err(Error::UnknownConstantTag(7))));
+
+        let mut bad = bytes.clone();
+        bad[6..10].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(loaded(&bad), Err(Error::TooManyConstants(_))));
+
+        // a code length far past the end of the file
+        let mut bad = bytes.clone();
+        bad[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(loaded(&bad), Err(Error::Truncated)));
+
+        let mut bad = bytes.clone();
+        let runs = bad.len() - 8;
+        bad[runs..runs + 4].copy_from_slice(&7u32.to_le_bytes());
+        assert!(matches!(
+            loaded(&bad),
+            Err(Error::LinesMismatch { lines: 7, code: 6 })
+        ));
+
+        let mut bad = bytes.clone();
+        let functions = bad.len() - 4;
+        bad[functions] = 1;
+        assert!(matches!(loaded(&bad), Err(Error::FunctionsUnsupported)));
+
+        let mut bad = bytes;
+        bad.push(0);
+        assert!(matches!(loaded(&bad), Err(Error::TrailingBytes)));
+    }
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(
+            "Not a compiled Lox file.",
+            loaded(b"1 + 2").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Unexpected end of file.",
+            loaded(b"LOX").unwrap_err().to_string()
+        );
+    }
+}
@@ -0,0 +1,8 @@
+pub mod chunk;
+pub mod compile;
+pub mod file;
+pub mod pratt;
+pub mod table;
+pub mod value;
+pub mod verify;
+pub mod vm;
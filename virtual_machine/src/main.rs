@@ -0,0 +1,207 @@
+use clap::{ArgGroup, Parser};
+use std::io::{stderr, stdin, stdout, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+
+use virtual_machine::chunk::Chunk;
+use virtual_machine::compile::{self, compile_source};
+use virtual_machine::file;
+use virtual_machine::pratt;
+use virtual_machine::value::Strings;
+use virtual_machine::verify::verify;
+use virtual_machine::vm::{InterpretResult, VirtualMachine};
+
+#[derive(Parser)]
+#[clap(version, group(ArgGroup::new("source").args(&["eval", "script"]).multiple(true)))]
+struct Args {
+    /// Lox code to run instead of a script
+    #[clap(short, long)]
+    eval: Option<String>,
+
+    /// Compile through tree_walk's parser instead of in a single pass
+    #[clap(long)]
+    ast: bool,
+
+    /// Print the compiled bytecode instead of running it
+    #[clap(long)]
+    disassemble: bool,
+
+    /// Print the stack and each instruction to stderr as it runs
+    #[clap(long)]
+    trace: bool,
+
+    /// Save the compiled bytecode to the --output file instead of running it;
+    /// needs a script or --eval, as the prompt has nothing to save
+    #[clap(long, requires_all = &["output", "source"])]
+    compile_only: bool,
+
+    /// Where --compile-only saves the bytecode
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+
+    /// Lox script, a compiled .loxc file, or - to read either from stdin
+    script: Option<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+    let runner = Runner {
+        compiler: if args.ast {
+            compile_source
+        } else {
+            pratt::compile
+        },
+        disassemble: args.disassemble,
+        trace: args.trace,
+        output: if args.compile_only { args.output } else { None },
+    };
+    let result = match (args.eval, args.script) {
+        (Some(source), _) => {
+            runner.run_script(&source);
+            Ok(())
+        }
+        (None, Some(script)) => {
+            if let Err(error) = runner.run_file(&script) {
+                eprintln!("Can't read {}: {}", script.display(), error);
+                std::process::exit(66);
+            }
+            Ok(())
+        }
+        (None, None) => runner.run_prompt(),
+    };
+    result.unwrap();
+}
+
+struct Runner {
+    compiler: fn(&str, &mut Strings) -> Result<Chunk, compile::Error>,
+    disassemble: bool,
+    trace: bool,
+    output: Option<PathBuf>,
+}
+
+impl Runner {
+    fn run_file(&self, script: &Path) -> std::io::Result<()> {
+        let mut bytes = Vec::new();
+        if script.as_os_str() == "-" {
+            stdin().read_to_end(&mut bytes)?;
+        } else {
+            bytes = std::fs::read(script)?;
+        }
+        if bytes.starts_with(file::MAGIC) {
+            let mut vm = self.vm();
+            let loaded = Chunk::read_from(&mut &bytes[..], vm.strings())
+                .map_err(|error| error.to_string())
+                .and_then(|chunk| match verify(&chunk) {
+                    Ok(()) => Ok(chunk),
+                    Err(error) => Err(error.to_string()),
+                });
+            match loaded {
+                Ok(chunk) => self.run_chunk(&mut vm, chunk),
+                Err(error) => {
+                    eprintln!("Can't load compiled file: {}", error);
+                    std::process::exit(65);
+                }
+            }
+        } else {
+            let source = String::from_utf8(bytes)
+                .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+            self.run_script(&source);
+        }
+        Ok(())
+    }
+
+    /// Leaves with 65 for a compile error and 70 for a runtime error, as
+    /// tree_walk does.
+    fn run_script(&self, source: &str) {
+        let mut vm = self.vm();
+        match (self.compiler)(source, vm.strings()) {
+            Ok(chunk) => self.run_chunk(&mut vm, chunk),
+            Err(error) => exit(report(error.into())),
+        }
+    }
+
+    fn run_chunk(&self, vm: &mut VirtualMachine, chunk: Chunk) {
+        let status = match &self.output {
+            Some(output) => {
+                let saved = std::fs::File::create(output)
+                    .map(std::io::BufWriter::new)
+                    .and_then(|mut file| {
+                        chunk.write_to(&mut file)?;
+                        file.flush()
+                    });
+                if let Err(error) = saved {
+                    eprintln!("Can't write {}: {}", output.display(), error);
+                    74
+                } else {
+                    0
+                }
+            }
+            None => report(self.execute(vm, &chunk)),
+        };
+        exit(status);
+    }
+
+    fn run_prompt(&self) -> std::io::Result<()> {
+        let mut vm = self.vm();
+        let mut lines = stdin().lock().lines();
+        loop {
+            print!("> ");
+            stdout().flush()?;
+            match lines.next() {
+                Some(line) => {
+                    report(self.interpret(&mut vm, &line?));
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn vm(&self) -> VirtualMachine {
+        if self.trace {
+            VirtualMachine::with_trace(stderr())
+        } else {
+            VirtualMachine::new()
+        }
+    }
+
+    fn interpret(&self, vm: &mut VirtualMachine, source: &str) -> InterpretResult {
+        match (self.compiler)(source, vm.strings()) {
+            Ok(chunk) => self.execute(vm, &chunk),
+            Err(error) => error.into(),
+        }
+    }
+
+    fn execute(&self, vm: &mut VirtualMachine, chunk: &Chunk) -> InterpretResult {
+        if self.disassemble {
+            print!("{}", chunk.disassemble());
+            InterpretResult::Ok(None)
+        } else {
+            vm.execute(chunk)
+        }
+    }
+}
+
+fn exit(status: i32) {
+    if status != 0 {
+        std::process::exit(status);
+    }
+}
+
+/// Prints the result and returns the status to exit with.
+fn report(result: InterpretResult) -> i32 {
+    match result {
+        InterpretResult::Ok(value) => {
+            if let Some(value) = value {
+                println!("{}", value);
+            }
+            0
+        }
+        InterpretResult::CompileError { message, line } => {
+            eprintln!("[line {}] Error: {}", line + 1, message);
+            65
+        }
+        InterpretResult::RuntimeError { message, line } => {
+            eprintln!("{}\n[line {}]", message, line + 1);
+            70
+        }
+    }
+}
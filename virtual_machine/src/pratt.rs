@@ -0,0 +1,463 @@
+//! clox's single-pass compiler: it pulls tokens from the scanner one at a
+//! time and emits bytecode as it goes, never building a tree. Operators are
+//! parsed by precedence climbing, with a prefix and an infix rule per token.
+//!
+//! It compiles the same programs to the same code as `compile`, and reports
+//! the same errors, on the same lines: a constant on its literal's, an
+//! operator on its token's, and a `Pop` or `Return` on the line of the
+//! instruction before it.
+
+use crate::chunk::{Chunk, OpCode};
+use crate::compile::{last_line, name_constant, variable, Error, Locals};
+use crate::value::{Strings, Value};
+use tree_walk::parse;
+use tree_walk::scan::{Source, Token, TokenType};
+
+/// Compiles a program whose last statement may be an expression with no
+/// semicolon, as tree_walk's prompt takes, returning that expression's value.
+/// Interns the chunk's strings in `strings`, which should be the VM's that
+/// will run it.
+pub fn compile(source: &str, strings: &mut Strings) -> Result<Chunk, Error> {
+    let mut compiler = Compiler {
+        tokens: Source::new(source.to_string()),
+        strings,
+        previous: Token::new(TokenType::EOF, 0),
+        current: Token::new(TokenType::EOF, 0),
+        chunk: Chunk::new(),
+        locals: Locals::default(),
+    };
+    compiler.advance()?;
+    while compiler.current.token_type() != TokenType::EOF {
+        if matches!(
+            compiler.current.token_type(),
+            TokenType::Var | TokenType::Print | TokenType::LeftBrace
+        ) {
+            compiler.declaration()?;
+            continue;
+        }
+        compiler.expression()?;
+        if compiler.current.token_type() == TokenType::EOF {
+            break;
+        }
+        compiler.expression_statement_end()?;
+    }
+    let line = last_line(&compiler.chunk);
+    compiler.chunk.add_instruction(OpCode::Return, line);
+    Ok(compiler.chunk)
+}
+
+/// Loosest first, so that a higher precedence compares greater.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Assignment,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Self {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+/// How tightly the token binds as an infix operator.
+fn infix_precedence(type_: &TokenType) -> Precedence {
+    match type_ {
+        TokenType::Equal => Precedence::Assignment,
+        TokenType::EqualEqual | TokenType::BangEqual => Precedence::Equality,
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+            Precedence::Comparison
+        }
+        TokenType::Minus | TokenType::Plus => Precedence::Term,
+        TokenType::Slash | TokenType::Star => Precedence::Factor,
+        TokenType::LeftParen => Precedence::Call,
+        _ => Precedence::None,
+    }
+}
+
+struct Compiler<'a> {
+    tokens: Source,
+    strings: &'a mut Strings,
+    previous: Token,
+    current: Token,
+    chunk: Chunk,
+    locals: Locals,
+}
+
+impl Compiler<'_> {
+    fn advance(&mut self) -> Result<(), Error> {
+        let next = match self.tokens.next() {
+            Some(token) => token.map_err(Error::Scan)?,
+            None => Token::new(TokenType::EOF, self.current.line()),
+        };
+        self.previous = std::mem::replace(&mut self.current, next);
+        Ok(())
+    }
+
+    fn declaration(&mut self) -> Result<(), Error> {
+        match self.current.token_type() {
+            TokenType::Var => self.var_declaration(),
+            TokenType::Print => Err(self.unsupported("print statements".to_string())),
+            TokenType::LeftBrace => self.block(),
+            _ => {
+                self.expression()?;
+                self.expression_statement_end()
+            }
+        }
+    }
+
+    fn var_declaration(&mut self) -> Result<(), Error> {
+        self.advance()?;
+        if !matches!(self.current.token_type(), TokenType::Identifier(_)) {
+            return Err(self.error_at_current(parse::Error::MissingVariableName));
+        }
+        self.advance()?;
+        let name = self.previous.clone();
+        let global = if self.locals.in_block() {
+            self.locals.declare(&name)?;
+            None
+        } else {
+            Some(name_constant(&mut self.chunk, self.strings, &name)?)
+        };
+        if self.current.token_type() == TokenType::Equal {
+            self.advance()?;
+            self.expression()?;
+        } else {
+            self.chunk.add_instruction(OpCode::Nil, name.line());
+        }
+        self.consume_semicolon()?;
+        match global {
+            Some(index) => {
+                self.chunk
+                    .add_index_instruction(OpCode::DefineGlobal, index, name.line())
+            }
+            None => self.locals.define(),
+        }
+        Ok(())
+    }
+
+    fn block(&mut self) -> Result<(), Error> {
+        self.advance()?;
+        self.locals.begin_block();
+        while !matches!(
+            self.current.token_type(),
+            TokenType::RightBrace | TokenType::EOF
+        ) {
+            self.declaration()?;
+        }
+        if self.current.token_type() != TokenType::RightBrace {
+            return Err(self.error_at_current(parse::Error::UnclosedBrace));
+        }
+        self.advance()?;
+        for _ in 0..self.locals.end_block() {
+            self.chunk
+                .add_instruction(OpCode::Pop, last_line(&self.chunk));
+        }
+        Ok(())
+    }
+
+    /// The semicolon after an expression statement, and the `Pop` that
+    /// throws its value away.
+    fn expression_statement_end(&mut self) -> Result<(), Error> {
+        self.consume_semicolon()?;
+        self.chunk
+            .add_instruction(OpCode::Pop, last_line(&self.chunk));
+        Ok(())
+    }
+
+    fn consume_semicolon(&mut self) -> Result<(), Error> {
+        if self.current.token_type() != TokenType::Semicolon {
+            return Err(self.error_at_current(parse::Error::MissingSemicolon));
+        }
+        self.advance()
+    }
+
+    fn expression(&mut self) -> Result<(), Error> {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), Error> {
+        // only the loosest expression can be an assignment's target
+        self.prefix(precedence <= Precedence::Assignment)?;
+        while precedence <= infix_precedence(&self.current.token_type()) {
+            self.advance()?;
+            self.infix()?;
+        }
+        Ok(())
+    }
+
+    fn prefix(&mut self, can_assign: bool) -> Result<(), Error> {
+        match self.current.token_type() {
+            TokenType::Number(n) => {
+                self.advance()?;
+                self.constant(Value::Number(n))?;
+            }
+            TokenType::Str(s) => {
+                self.advance()?;
+                let string = self.strings.intern(&s);
+                self.constant(Value::String(string))?;
+            }
+            TokenType::LeftParen => {
+                self.advance()?;
+                self.expression()?;
+                if self.current.token_type() != TokenType::RightParen {
+                    return Err(self.error_at_current(parse::Error::UnclosedParen));
+                }
+                self.advance()?;
+            }
+            TokenType::Minus => {
+                self.advance()?;
+                let operator = self.previous.clone();
+                self.parse_precedence(Precedence::Unary)?;
+                self.chunk.add_instruction(OpCode::Negate, operator.line());
+            }
+            TokenType::Nil => {
+                self.advance()?;
+                self.chunk
+                    .add_instruction(OpCode::Nil, self.previous.line());
+            }
+            TokenType::Bang | TokenType::True | TokenType::False => {
+                return Err(self.unsupported(format!("'{}'", self.current)));
+            }
+            TokenType::Identifier(_) => {
+                self.advance()?;
+                let name = self.previous.clone();
+                let (get, set, operand) =
+                    variable(&mut self.chunk, self.strings, &self.locals, &name)?;
+                if can_assign && self.current.token_type() == TokenType::Equal {
+                    self.advance()?;
+                    self.expression()?;
+                    self.chunk.add_index_instruction(set, operand, name.line());
+                } else {
+                    self.chunk.add_index_instruction(get, operand, name.line());
+                }
+            }
+            // left where it is, like the tree_walk parser does
+            _ => return Err(self.error_at_current(parse::Error::NoExpression)),
+        }
+        Ok(())
+    }
+
+    fn infix(&mut self) -> Result<(), Error> {
+        let operator = self.previous.clone();
+        let opcode = match operator.token_type() {
+            TokenType::Plus => OpCode::Add,
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Slash => OpCode::Divide,
+            // a variable would have taken the `=` as its assignment
+            TokenType::Equal => {
+                return Err(Error::Parse {
+                    error: parse::Error::InvalidAssignmentTarget,
+                    line: operator.line(),
+                })
+            }
+            TokenType::LeftParen => {
+                return Err(Error::Unsupported {
+                    what: "calls".to_string(),
+                    line: operator.line(),
+                })
+            }
+            _ => {
+                return Err(Error::Unsupported {
+                    what: format!("'{}'", operator),
+                    line: operator.line(),
+                })
+            }
+        };
+        self.parse_precedence(infix_precedence(&operator.token_type()).next())?;
+        self.chunk.add_instruction(opcode, operator.line());
+        Ok(())
+    }
+
+    /// A constant on the line of the token just consumed.
+    fn constant(&mut self, value: Value) -> Result<(), Error> {
+        let line = self.previous.line();
+        match self.chunk.add_constant(value, line) {
+            Some(_) => Ok(()),
+            None => Err(Error::TooManyConstants { line }),
+        }
+    }
+
+    fn error_at_current(&self, error: parse::Error) -> Error {
+        Error::Parse {
+            error,
+            line: self.current.line(),
+        }
+    }
+
+    fn unsupported(&self, what: String) -> Error {
+        Error::Unsupported {
+            what,
+            line: self.current.line(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compiled(source: &str) -> Result<Chunk, Error> {
+        compile(source, &mut Strings::new())
+    }
+
+    fn ast(source: &str) -> Result<Chunk, Error> {
+        crate::compile::compile_source(source, &mut Strings::new())
+    }
+
+    fn lines(chunk: &Chunk) -> Vec<usize> {
+        (0..chunk.code().len()).map(|i| chunk.get_line(i)).collect()
+    }
+
+    // Compiles with both compilers into the same strings, so the interned
+    // constants compare equal.
+    fn assert_same_as_ast(source: &str) {
+        let mut strings = Strings::new();
+        let single_pass = compile(source, &mut strings).unwrap();
+        let ast = crate::compile::compile_source(source, &mut strings).unwrap();
+        assert_eq!(ast.code(), single_pass.code(), "{}", source);
+        assert_eq!(ast.constants(), single_pass.constants(), "{}", source);
+        assert_eq!(lines(&ast), lines(&single_pass), "{}", source);
+    }
+
+    const SOURCES: [&str; 28] = [
+        "1",
+        "-1",
+        "1 + 2 * -3",
+        "(1 + 2) * 3",
+        "1 - 2 - 3",
+        "1 / 2 / 3 * 4",
+        "--(((5)))",
+        "1 * 2 + 3 * 4 - 5 / 6",
+        "-(1.5 - -2) / (3 + 4 * (5 - 6))",
+        "1 +\n2 *\n\n3",
+        "1 + 2 - 1 * 2 / 3",
+        "\"a\"",
+        "\"a\" + (\"b\" + \"a\") + \"\"",
+        "",
+        "nil",
+        "var a = 1; var b; a = b = a + 2; a",
+        "a; b = -c;",
+        "var a; var a = a;",
+        "{ var a = 1; { var b = a; b = a = 2; } var c; c; }",
+        "{ var a = 1; { var b = a; var a = 2; a; } a; }",
+        "var a = 1; { var b = a + 1; a = \"a\"; } a",
+        "{}{{}}",
+        "1;\n2;\n(3)",
+        "var a =\n1;\n{ var b;\na; }\na",
+        "(\n1\n+\n2\n)\n;",
+        "{ var a\n=\nnil;\n}\n",
+        "var a;\na\n=\n-\n\"a\"",
+        "var\na\n;",
+    ];
+
+    #[test]
+    fn test_same_code_as_ast() {
+        for source in SOURCES {
+            assert_same_as_ast(source);
+        }
+    }
+
+    #[test]
+    fn test_same_long_constants_as_ast() {
+        let numbers: Vec<String> = (0..300).map(|n| (n % 280).to_string()).collect();
+        let source = numbers.join(" + ");
+        assert_same_as_ast(&source);
+        assert_eq!(280, compiled(&source).unwrap().constants().len());
+
+        let declarations: Vec<String> = (0..300).map(|n| format!("var a{} = a0;", n)).collect();
+        assert_same_as_ast(&format!("{} a299 = a1;", declarations.join("\n")));
+    }
+
+    #[test]
+    fn test_same_errors_as_ast() {
+        for source in [
+            "1 +",
+            "(1",
+            "1 2",
+            "1)",
+            "#",
+            "\"a",
+            "!1",
+            "1 < 2",
+            "1 == 2",
+            "true",
+            "a()",
+            "1 = 2",
+            "a + b = 2",
+            "-a = 2",
+            "a = 1 = 2",
+            "(1) +",
+            "1 + (2 * )",
+            "var",
+            "var 1;",
+            "var a = 1",
+            "var a = 1 2;",
+            "1; 2 3",
+            "{ 1 }",
+            "{ var a; ",
+            "print 1;",
+            "{ var a; var a; }",
+            "{ var a = a; }",
+            "{ var a = 1; { var a = a; } }",
+            "{ var b = 1; { var a = b = a; } }",
+        ] {
+            let single_pass = compiled(source).unwrap_err();
+            let ast = ast(source).unwrap_err();
+            assert_eq!(ast.to_string(), single_pass.to_string(), "{}", source);
+            assert_eq!(ast.line(), single_pass.line(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_lines() {
+        let chunk = compiled("1 +\n2").unwrap();
+        assert_eq!(0, chunk.get_line(0));
+        assert_eq!(1, chunk.get_line(2));
+        // the operator's line
+        assert_eq!(0, chunk.get_line(4));
+
+        assert_eq!(
+            Err(Error::Parse {
+                error: parse::Error::UnclosedParen,
+                line: 2
+            }),
+            compiled("(1\n+\n2")
+        );
+        assert_eq!(2, compiled("1 +\n\ntrue").unwrap_err().line());
+    }
+
+    #[test]
+    fn test_variable_lines() {
+        let chunk = compiled("var a =\n1;\n{ var b;\na; }\na").unwrap();
+        // a global's instructions are on its name's line, and a block's pops
+        // on the line of its last instruction
+        assert_eq!(&[8, 1, 12, 0, 10, 13, 0, 11, 11, 13, 0, 1], chunk.code());
+        let lines: Vec<usize> = (0..chunk.code().len()).map(|i| chunk.get_line(i)).collect();
+        assert_eq!(vec![1, 1, 0, 0, 2, 3, 3, 3, 3, 4, 4, 4], lines);
+    }
+
+    #[test]
+    fn test_too_many_locals() {
+        let declarations: Vec<String> = (0..256).map(|n| format!("var a{};", n)).collect();
+        let source = format!("{{ {}\nvar b; }}", declarations.join(" "));
+        assert_eq!(ast(&source), compiled(&source));
+        assert_eq!(Err(Error::TooManyLocals { line: 1 }), compiled(&source));
+    }
+}
@@ -0,0 +1,255 @@
+//! clox's hash table: open addressing with linear probing, keyed by
+//! interned strings. Since equal strings are the same object, keys compare by
+//! pointer and hash by the hash the string computed when it was made.
+
+use crate::value::ObjString;
+use std::rc::Rc;
+
+/// Grow once more than this share of the slots are taken.
+const MAX_LOAD: f64 = 0.75;
+
+#[derive(Clone, Debug)]
+enum Slot<V> {
+    Empty,
+    /// Where a deleted entry was, so that probing carries on past it.
+    Tombstone,
+    Full(Rc<ObjString>, V),
+}
+
+#[derive(Clone, Debug)]
+pub struct Table<V> {
+    slots: Vec<Slot<V>>,
+    /// Full slots and tombstones: everything that makes probes longer.
+    used: usize,
+    len: usize,
+}
+
+impl<V> Default for Table<V> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            used: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<V> Table<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &Rc<ObjString>) -> Option<&V> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        match &self.slots[self.find(key)] {
+            Slot::Full(_, value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &Rc<ObjString>) -> Option<&mut V> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let index = self.find(key);
+        match &mut self.slots[index] {
+            Slot::Full(_, value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Adds or replaces the key's value. True if the key is new.
+    pub fn set(&mut self, key: Rc<ObjString>, value: V) -> bool {
+        if (self.used + 1) as f64 > self.slots.len() as f64 * MAX_LOAD {
+            self.grow();
+        }
+        let index = self.find(&key);
+        let new = !matches!(self.slots[index], Slot::Full(..));
+        if matches!(self.slots[index], Slot::Empty) {
+            self.used += 1;
+        }
+        if new {
+            self.len += 1;
+        }
+        self.slots[index] = Slot::Full(key, value);
+        new
+    }
+
+    /// Removes the key, leaving a tombstone. True if it was there.
+    pub fn delete(&mut self, key: &Rc<ObjString>) -> bool {
+        if self.slots.is_empty() {
+            return false;
+        }
+        let index = self.find(key);
+        if !matches!(self.slots[index], Slot::Full(..)) {
+            return false;
+        }
+        self.slots[index] = Slot::Tombstone;
+        self.len -= 1;
+        true
+    }
+
+    /// The key with these characters, if there is one. Interning uses this
+    /// to find a string before there's an object to compare pointers with.
+    pub fn find_string(&self, chars: &str, hash: u32) -> Option<&Rc<ObjString>> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let mut index = hash as usize & (self.slots.len() - 1);
+        loop {
+            match &self.slots[index] {
+                Slot::Empty => return None,
+                Slot::Full(key, _) if key.hash() == hash && key.as_str() == chars => {
+                    return Some(key)
+                }
+                _ => {}
+            }
+            index = (index + 1) & (self.slots.len() - 1);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Rc<ObjString>, &V)> {
+        self.slots.iter().filter_map(|slot| match slot {
+            Slot::Full(key, value) => Some((key, value)),
+            _ => None,
+        })
+    }
+
+    /// The key's slot, or where it would go: the first tombstone passed on
+    /// the way, so they get reused, or else the empty slot that ended the
+    /// probe. There's always an empty slot, as the table never fills up.
+    fn find(&self, key: &Rc<ObjString>) -> usize {
+        let mut index = key.hash() as usize & (self.slots.len() - 1);
+        let mut tombstone = None;
+        loop {
+            match &self.slots[index] {
+                Slot::Empty => return tombstone.unwrap_or(index),
+                Slot::Tombstone => {
+                    tombstone.get_or_insert(index);
+                }
+                Slot::Full(other, _) if Rc::ptr_eq(key, other) => return index,
+                Slot::Full(..) => {}
+            }
+            index = (index + 1) & (self.slots.len() - 1);
+        }
+    }
+
+    /// Doubles the slots, dropping tombstones as the entries move over.
+    fn grow(&mut self) {
+        let capacity = (self.slots.len() * 2).max(8);
+        let slots = std::mem::replace(
+            &mut self.slots,
+            (0..capacity).map(|_| Slot::Empty).collect(),
+        );
+        self.used = 0;
+        self.len = 0;
+        for slot in slots {
+            if let Slot::Full(key, value) = slot {
+                let index = self.find(&key);
+                self.slots[index] = Slot::Full(key, value);
+                self.used += 1;
+                self.len += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Strings;
+
+    #[test]
+    fn test_set_get_delete() {
+        let mut strings = Strings::new();
+        let (a, b) = (strings.intern("a"), strings.intern("b"));
+        let mut table = Table::new();
+        assert_eq!(None, table.get(&a));
+        assert!(table.set(a.clone(), 1));
+        assert!(!table.set(a.clone(), 2));
+        assert!(table.set(b.clone(), 3));
+        assert_eq!(Some(&2), table.get(&a));
+        assert_eq!(2, table.len());
+
+        assert!(table.delete(&a));
+        assert!(!table.delete(&a));
+        assert_eq!(None, table.get(&a));
+        assert_eq!(Some(&3), table.get(&b));
+        assert_eq!(1, table.len());
+
+        *table.get_mut(&b).unwrap() = 4;
+        assert_eq!(Some(&4), table.get(&b));
+    }
+
+    #[test]
+    fn test_grows() {
+        let mut strings = Strings::new();
+        let keys: Vec<_> = (0..1000).map(|n| strings.intern(&n.to_string())).collect();
+        let mut table = Table::new();
+        for (n, key) in keys.iter().enumerate() {
+            table.set(key.clone(), n);
+        }
+        assert_eq!(1000, table.len());
+        for (n, key) in keys.iter().enumerate() {
+            assert_eq!(Some(&n), table.get(key));
+        }
+        assert!(table.slots.len() as f64 * MAX_LOAD >= 1000.0);
+        assert_eq!(1000, table.iter().count());
+    }
+
+    #[test]
+    fn test_probes_past_tombstones() {
+        let mut strings = Strings::new();
+        let keys: Vec<_> = (0..6).map(|n| strings.intern(&n.to_string())).collect();
+        let mut table = Table::new();
+        for key in &keys {
+            table.set(key.clone(), ());
+        }
+        // whichever keys collided, the later ones are still found
+        for key in &keys[..3] {
+            table.delete(key);
+        }
+        for key in &keys[3..] {
+            assert_eq!(Some(&()), table.get(key));
+        }
+        // and the tombstones are reused
+        let used = table.used;
+        table.set(keys[0].clone(), ());
+        assert!(table.used <= used);
+    }
+
+    #[test]
+    fn test_tombstones_count_towards_load() {
+        let mut strings = Strings::new();
+        let mut table = Table::new();
+        for n in 0..100 {
+            let key = strings.intern(&n.to_string());
+            table.set(key.clone(), ());
+            table.delete(&key);
+        }
+        assert!(table.is_empty());
+        // an empty slot is always left to stop probes for missing keys
+        assert_eq!(None, table.get(&strings.intern("missing")));
+    }
+
+    #[test]
+    fn test_find_string() {
+        let mut strings = Strings::new();
+        let key = strings.intern("key");
+        let mut table = Table::new();
+        table.set(key.clone(), ());
+        let found = table.find_string("key", key.hash()).unwrap();
+        assert!(Rc::ptr_eq(&key, found));
+        assert!(table.find_string("kez", key.hash()).is_none());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::table::Table;
+use std::rc::Rc;
+
+/// What the VM's stack holds.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Nil,
+    Number(f32),
+    /// Always interned, so two strings are equal only if they're the same
+    /// object.
+    String(Rc<ObjString>),
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s.as_str()),
+        }
+    }
+}
+
+/// A string on the heap, with its hash worked out once when it's made.
+#[derive(Debug)]
+pub struct ObjString {
+    chars: Box<str>,
+    hash: u32,
+}
+
+impl ObjString {
+    pub fn as_str(&self) -> &str {
+        &self.chars
+    }
+
+    pub fn hash(&self) -> u32 {
+        self.hash
+    }
+}
+
+/// FNV-1a, as clox uses.
+pub fn hash_string(chars: &str) -> u32 {
+    chars.bytes().fold(2166136261, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(16777619)
+    })
+}
+
+/// Every string the VM has made, so that making one that already exists
+/// gives back the same object.
+#[derive(Default)]
+pub struct Strings {
+    table: Table<()>,
+}
+
+impl Strings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, chars: &str) -> Rc<ObjString> {
+        let hash = hash_string(chars);
+        if let Some(string) = self.table.find_string(chars, hash) {
+            return string.clone();
+        }
+        let string = Rc::new(ObjString {
+            chars: chars.into(),
+            hash,
+        });
+        self.table.set(string.clone(), ());
+        string
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!("nil", Value::Nil.to_string());
+        assert_eq!("5", Value::Number(5.0).to_string());
+        assert_eq!("-2.5", Value::Number(-2.5).to_string());
+        assert_eq!("hi", Value::String(Strings::new().intern("hi")).to_string());
+    }
+
+    #[test]
+    fn test_interning() {
+        let mut strings = Strings::new();
+        let a = strings.intern("hello");
+        let b = strings.intern(&format!("hel{}", "lo"));
+        assert!(Rc::ptr_eq(&a, &b));
+        assert_eq!(Value::String(a.clone()), Value::String(b));
+        assert_ne!(Value::String(a), Value::String(strings.intern("world")));
+        assert_eq!(2, strings.len());
+    }
+
+    #[test]
+    fn test_equal_only_if_interned_together() {
+        let a = Strings::new().intern("same");
+        let b = Strings::new().intern("same");
+        assert_ne!(Value::String(a), Value::String(b));
+        assert_ne!(
+            Value::Number(1.0),
+            Value::String(Strings::new().intern("1"))
+        );
+    }
+
+    #[test]
+    fn test_hash() {
+        // FNV-1a test vectors
+        assert_eq!(0x811c9dc5, hash_string(""));
+        assert_eq!(0xe40c292c, hash_string("a"));
+        assert_eq!(0xbf9cf968, hash_string("foobar"));
+    }
+}
@@ -0,0 +1,334 @@
+//! Checks a chunk from outside the compiler, such as a loaded `.loxc` file,
+//! before the VM runs it: every instruction has to decode, load constants the
+//! chunk has, name globals with strings, read locals that are on the stack,
+//! keep the stack from underflowing and end in a `Return`.
+//!
+//! There are no jumps yet, so the instructions are walked in order, tracking
+//! the stack depth. Once there are, each path will need walking, and paths
+//! that meet with different depths will need catching.
+
+use crate::chunk::{Chunk, OpCode};
+use crate::value::Value;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    UnknownOpcode {
+        byte: u8,
+        offset: usize,
+    },
+    TruncatedOperand {
+        offset: usize,
+    },
+    UnknownConstant {
+        index: usize,
+        offset: usize,
+    },
+    /// A global instruction whose name constant isn't a string.
+    NotAName {
+        index: usize,
+        offset: usize,
+    },
+    /// A local instruction whose slot is past the top of the stack.
+    UnknownLocal {
+        slot: usize,
+        offset: usize,
+    },
+    StackUnderflow {
+        offset: usize,
+    },
+    /// Control runs off the end of the code.
+    MissingReturn {
+        offset: usize,
+    },
+}
+
+impl Error {
+    pub fn offset(&self) -> usize {
+        match self {
+            Error::UnknownOpcode { offset, .. }
+            | Error::TruncatedOperand { offset }
+            | Error::UnknownConstant { offset, .. }
+            | Error::NotAName { offset, .. }
+            | Error::UnknownLocal { offset, .. }
+            | Error::StackUnderflow { offset }
+            | Error::MissingReturn { offset } => *offset,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnknownOpcode { byte, .. } => write!(f, "Unknown opcode {}", byte),
+            Error::TruncatedOperand { .. } => write!(f, "Truncated operand"),
+            Error::UnknownConstant { index, .. } => write!(f, "Unknown constant {}", index),
+            Error::NotAName { index, .. } => write!(f, "Constant {} isn't a name", index),
+            Error::UnknownLocal { slot, .. } => write!(f, "Unknown local {}", slot),
+            Error::StackUnderflow { .. } => write!(f, "Stack underflow"),
+            Error::MissingReturn { .. } => write!(f, "Missing return"),
+        }?;
+        write!(f, " at offset {}.", self.offset())
+    }
+}
+
+pub fn verify(chunk: &Chunk) -> Result<(), Error> {
+    let code = chunk.code();
+    let mut offset = 0;
+    let mut depth = 0;
+    loop {
+        if offset >= code.len() {
+            return Err(Error::MissingReturn { offset });
+        }
+        let opcode =
+            OpCode::try_from(code[offset]).map_err(|byte| Error::UnknownOpcode { byte, offset })?;
+        let (pops, pushes, width) = match opcode {
+            OpCode::Return => return Ok(()),
+            OpCode::Immediate => (0, 1, 4),
+            OpCode::Constant | OpCode::ConstantLong => {
+                let width = opcode.index_width();
+                let index = chunk
+                    .operand(offset + 1, width)
+                    .ok_or(Error::TruncatedOperand { offset })?;
+                if index >= chunk.constants().len() {
+                    return Err(Error::UnknownConstant { index, offset });
+                }
+                (0, 1, width)
+            }
+            OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::DefineGlobalLong
+            | OpCode::GetGlobalLong
+            | OpCode::SetGlobalLong => {
+                let width = opcode.index_width();
+                let index = chunk
+                    .operand(offset + 1, width)
+                    .ok_or(Error::TruncatedOperand { offset })?;
+                match chunk.constants().get(index) {
+                    Some(Value::String(_)) => {}
+                    Some(_) => return Err(Error::NotAName { index, offset }),
+                    None => return Err(Error::UnknownConstant { index, offset }),
+                }
+                match opcode {
+                    OpCode::DefineGlobal | OpCode::DefineGlobalLong => (1, 0, width),
+                    OpCode::GetGlobal | OpCode::GetGlobalLong => (0, 1, width),
+                    _ => (1, 1, width),
+                }
+            }
+            OpCode::GetLocal | OpCode::SetLocal => {
+                let slot = chunk
+                    .operand(offset + 1, 1)
+                    .ok_or(Error::TruncatedOperand { offset })?;
+                if slot >= depth {
+                    return Err(Error::UnknownLocal { slot, offset });
+                }
+                if opcode == OpCode::GetLocal {
+                    (0, 1, 1)
+                } else {
+                    (1, 1, 1)
+                }
+            }
+            OpCode::Nil => (0, 1, 0),
+            OpCode::Pop => (1, 0, 0),
+            OpCode::Negate => (1, 1, 0),
+            OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide => (2, 1, 0),
+        };
+        if offset + 1 + width > code.len() {
+            return Err(Error::TruncatedOperand { offset });
+        }
+        if depth < pops {
+            return Err(Error::StackUnderflow { offset });
+        }
+        offset += 1 + width;
+        depth = depth - pops + pushes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pratt;
+    use crate::value::Strings;
+
+    fn compiled(source: &str) -> Result<Chunk, crate::compile::Error> {
+        pratt::compile(source, &mut Strings::new())
+    }
+
+    fn chunk(opcodes: &[OpCode]) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Number(1.0), 0);
+        for opcode in opcodes {
+            chunk.add_instruction(*opcode, 0);
+        }
+        chunk
+    }
+
+    #[test]
+    fn test_compiled_chunks_pass() {
+        for source in [
+            "1",
+            "-(1 + 2) * 3 / 4 - 5",
+            "1 + 2 + 3 + 1",
+            "var a = 1; { var b = a; { var c; c = b = a = nil; } } a",
+        ] {
+            assert_eq!(Ok(()), verify(&compiled(source).unwrap()), "{}", source);
+        }
+        let mut long = Chunk::new();
+        for n in 0..300 {
+            long.add_constant(Value::Number(n as f32), 0);
+        }
+        long.add_instruction(OpCode::Return, 0);
+        assert_eq!(Ok(()), verify(&long));
+
+        let mut immediate = Chunk::new();
+        immediate.add_immediate(1.0, 0);
+        immediate.add_instruction(OpCode::Return, 0);
+        assert_eq!(Ok(()), verify(&immediate));
+    }
+
+    #[test]
+    fn test_underflow() {
+        assert_eq!(
+            Err(Error::StackUnderflow { offset: 2 }),
+            verify(&chunk(&[OpCode::Add, OpCode::Return]))
+        );
+        assert_eq!(
+            Err(Error::StackUnderflow { offset: 0 }),
+            verify(&Chunk::from_parts(vec![3, 1], vec![(0, 2)], vec![]))
+        );
+    }
+
+    #[test]
+    fn test_missing_return() {
+        assert_eq!(
+            Err(Error::MissingReturn { offset: 0 }),
+            verify(&Chunk::new())
+        );
+        assert_eq!(
+            Err(Error::MissingReturn { offset: 3 }),
+            verify(&chunk(&[OpCode::Negate]))
+        );
+    }
+
+    #[test]
+    fn test_operands() {
+        // Return's byte read as the index of a constant that isn't there
+        assert_eq!(
+            Err(Error::UnknownConstant {
+                index: 1,
+                offset: 2
+            }),
+            verify(&chunk(&[OpCode::Constant, OpCode::Return]))
+        );
+        assert_eq!(
+            Err(Error::TruncatedOperand { offset: 2 }),
+            verify(&chunk(&[OpCode::ConstantLong, OpCode::Return]))
+        );
+        assert_eq!(
+            Err(Error::TruncatedOperand { offset: 0 }),
+            verify(&Chunk::from_parts(vec![2, 0, 0], vec![(0, 3)], vec![]))
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut strings = Strings::new();
+        let name = Value::String(strings.intern("a"));
+        // Nil, GetLocal 1
+        assert_eq!(
+            Err(Error::UnknownLocal { slot: 1, offset: 1 }),
+            verify(&Chunk::from_parts(vec![10, 15, 1, 1], vec![(0, 4)], vec![]))
+        );
+        // Nil, SetLocal 0, Pop, Pop
+        assert_eq!(
+            Err(Error::StackUnderflow { offset: 4 }),
+            verify(&Chunk::from_parts(
+                vec![10, 16, 0, 11, 11, 1],
+                vec![(0, 6)],
+                vec![]
+            ))
+        );
+        // GetGlobal of a number, then of a string
+        assert_eq!(
+            Err(Error::NotAName {
+                index: 0,
+                offset: 0
+            }),
+            verify(&Chunk::from_parts(
+                vec![13, 0, 1],
+                vec![(0, 3)],
+                vec![Value::Number(1.0)]
+            ))
+        );
+        assert_eq!(
+            Ok(()),
+            verify(&Chunk::from_parts(
+                vec![13, 0, 14, 0, 12, 0, 1],
+                vec![(0, 7)],
+                vec![name.clone()]
+            ))
+        );
+        // DefineGlobal with nothing to define
+        assert_eq!(
+            Err(Error::StackUnderflow { offset: 0 }),
+            verify(&Chunk::from_parts(
+                vec![12, 0, 1],
+                vec![(0, 3)],
+                vec![name.clone()]
+            ))
+        );
+        assert_eq!(
+            Err(Error::TruncatedOperand { offset: 1 }),
+            verify(&Chunk::from_parts(vec![10, 13], vec![(0, 2)], vec![]))
+        );
+        // the long forms, with three-byte indexes
+        assert_eq!(
+            Ok(()),
+            verify(&Chunk::from_parts(
+                vec![18, 0, 0, 0, 19, 0, 0, 0, 17, 0, 0, 0, 1],
+                vec![(0, 13)],
+                vec![name.clone()]
+            ))
+        );
+        assert_eq!(
+            Err(Error::TruncatedOperand { offset: 0 }),
+            verify(&Chunk::from_parts(vec![18, 0, 0], vec![(0, 3)], vec![name]))
+        );
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        assert_eq!(
+            Err(Error::UnknownOpcode {
+                byte: 0xff,
+                offset: 2
+            }),
+            verify(&Chunk::from_parts(
+                vec![8, 0, 0xff, 1],
+                vec![(0, 4)],
+                vec![Value::Number(1.0)]
+            ))
+        );
+        // code after a Return is never reached, so it isn't checked
+        assert_eq!(
+            Ok(()),
+            verify(&Chunk::from_parts(vec![1, 0xff], vec![(0, 2)], vec![]))
+        );
+    }
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(
+            "Stack underflow at offset 2.",
+            verify(&chunk(&[OpCode::Add])).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Constant 0 isn't a name at offset 2.",
+            Error::NotAName {
+                index: 0,
+                offset: 2
+            }
+            .to_string()
+        );
+    }
+}
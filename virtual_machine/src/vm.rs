@@ -0,0 +1,591 @@
+use crate::chunk::{Chunk, OpCode};
+use crate::compile;
+use crate::pratt;
+use crate::table::Table;
+use crate::value::{ObjString, Strings, Value};
+use std::io::Write;
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq)]
+pub enum InterpretResult {
+    /// What `Return` popped, if the stack had anything on it.
+    Ok(Option<Value>),
+    CompileError {
+        message: String,
+        line: usize,
+    },
+    RuntimeError {
+        message: String,
+        line: usize,
+    },
+}
+
+impl From<compile::Error> for InterpretResult {
+    fn from(error: compile::Error) -> Self {
+        InterpretResult::CompileError {
+            message: error.to_string(),
+            line: error.line(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct VirtualMachine {
+    stack: Vec<Value>,
+    /// Every string the VM's chunks and their code have made.
+    strings: Strings,
+    /// Kept from one chunk to the next, as the prompt runs each line as its
+    /// own chunk.
+    globals: Table<Value>,
+    /// Where to write the stack and the instruction before each one runs,
+    /// like clox's DEBUG_TRACE_EXECUTION.
+    trace: Option<Box<dyn Write>>,
+}
+
+impl VirtualMachine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_trace(trace: impl Write + 'static) -> Self {
+        Self {
+            trace: Some(Box::new(trace)),
+            ..Self::default()
+        }
+    }
+
+    /// Where chunks this VM runs should intern their strings, for them to
+    /// compare equal to the ones it makes.
+    pub fn strings(&mut self) -> &mut Strings {
+        &mut self.strings
+    }
+
+    /// Compiles a program in a single pass and runs it.
+    pub fn interpret(&mut self, source: &str) -> InterpretResult {
+        match pratt::compile(source, &mut self.strings) {
+            Ok(chunk) => self.execute(&chunk),
+            Err(error) => error.into(),
+        }
+    }
+
+    pub fn execute(&mut self, chunk: &Chunk) -> InterpretResult {
+        self.stack.clear();
+        match self.run(chunk) {
+            Ok(value) => InterpretResult::Ok(value),
+            Err((message, offset)) => InterpretResult::RuntimeError {
+                message,
+                line: chunk.get_line(offset),
+            },
+        }
+    }
+
+    /// Errors carry the offset of the instruction that failed, so the line
+    /// table is only searched when something goes wrong.
+    fn run(&mut self, chunk: &Chunk) -> Result<Option<Value>, (String, usize)> {
+        let code = chunk.code();
+        let mut ip = 0;
+        while ip < code.len() {
+            let start = ip;
+            let error = |message: &str| (message.to_string(), start);
+            self.trace(chunk, ip);
+            let opcode = OpCode::try_from(code[ip])
+                .map_err(|byte| (format!("Unknown opcode {}.", byte), start))?;
+            ip += 1;
+            match opcode {
+                OpCode::Return => return Ok(self.stack.pop()),
+                OpCode::Immediate => {
+                    let bytes = code
+                        .get(ip..ip + 4)
+                        .ok_or_else(|| error("Truncated immediate."))?;
+                    let n = f32::from_le_bytes(bytes.try_into().unwrap());
+                    self.stack.push(Value::Number(n));
+                    ip += 4;
+                }
+                OpCode::Constant | OpCode::ConstantLong => {
+                    let width = opcode.index_width();
+                    let index = chunk
+                        .operand(ip, width)
+                        .ok_or_else(|| error("Truncated constant."))?;
+                    let value = chunk
+                        .constants()
+                        .get(index)
+                        .ok_or_else(|| (format!("Unknown constant {}.", index), start))?;
+                    self.stack.push(value.clone());
+                    ip += width;
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::Pop => {
+                    self.stack.pop().ok_or_else(|| error("Stack underflow."))?;
+                }
+                OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                    let name = global_name(chunk, opcode, ip, start)?;
+                    let value = self.stack.pop().ok_or_else(|| error("Stack underflow."))?;
+                    self.globals.set(name, value);
+                    ip += opcode.index_width();
+                }
+                OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                    let name = global_name(chunk, opcode, ip, start)?;
+                    let value = self
+                        .globals
+                        .get(&name)
+                        .ok_or_else(|| undefined(&name, start))?;
+                    self.stack.push(value.clone());
+                    ip += opcode.index_width();
+                }
+                OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                    let name = global_name(chunk, opcode, ip, start)?;
+                    let value = self.stack.last().ok_or_else(|| error("Stack underflow."))?;
+                    // assigning doesn't declare, so a new key was a mistake
+                    if self.globals.set(name.clone(), value.clone()) {
+                        self.globals.delete(&name);
+                        return Err(undefined(&name, start));
+                    }
+                    ip += opcode.index_width();
+                }
+                OpCode::GetLocal | OpCode::SetLocal => {
+                    let slot = chunk
+                        .operand(ip, 1)
+                        .ok_or_else(|| error("Truncated local."))?;
+                    if slot >= self.stack.len() {
+                        return Err((format!("Unknown local {}.", slot), start));
+                    }
+                    if opcode == OpCode::GetLocal {
+                        self.stack.push(self.stack[slot].clone());
+                    } else {
+                        self.stack[slot] = self.stack[self.stack.len() - 1].clone();
+                    }
+                    ip += 1;
+                }
+                OpCode::Negate => match self.stack.pop() {
+                    Some(Value::Number(n)) => self.stack.push(Value::Number(-n)),
+                    Some(_) => return Err(error("Operand must be a number.")),
+                    None => return Err(error("Stack underflow.")),
+                },
+                OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide => {
+                    let (b, a) = self
+                        .stack
+                        .pop()
+                        .zip(self.stack.pop())
+                        .ok_or_else(|| error("Stack underflow."))?;
+                    let result = match (opcode, a, b) {
+                        (OpCode::Add, Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+                        (OpCode::Add, Value::String(a), Value::String(b)) => {
+                            let concatenated = [a.as_str(), b.as_str()].concat();
+                            Value::String(self.strings.intern(&concatenated))
+                        }
+                        (OpCode::Add, ..) => {
+                            return Err(error("Operands must be two numbers or two strings."))
+                        }
+                        (OpCode::Subtract, Value::Number(a), Value::Number(b)) => {
+                            Value::Number(a - b)
+                        }
+                        (OpCode::Multiply, Value::Number(a), Value::Number(b)) => {
+                            Value::Number(a * b)
+                        }
+                        (OpCode::Divide, Value::Number(a), Value::Number(b)) => {
+                            Value::Number(a / b)
+                        }
+                        _ => return Err(error("Operands must be numbers.")),
+                    };
+                    self.stack.push(result);
+                }
+            }
+        }
+        Err(("Missing return.".to_string(), code.len()))
+    }
+
+    fn trace(&mut self, chunk: &Chunk, ip: usize) {
+        if let Some(trace) = &mut self.trace {
+            let stack: String = self.stack.iter().map(|v| format!("[ {} ]", v)).collect();
+            let (instruction, _) = chunk.disassemble_instruction(ip);
+            // a trace that can't be written shouldn't stop the program
+            let _ = writeln!(trace, "          {}\n{}", stack, instruction);
+        }
+    }
+}
+
+/// The name a global instruction's operand points to in the constant table.
+fn global_name(
+    chunk: &Chunk,
+    opcode: OpCode,
+    ip: usize,
+    start: usize,
+) -> Result<Rc<ObjString>, (String, usize)> {
+    let index = chunk
+        .operand(ip, opcode.index_width())
+        .ok_or_else(|| ("Truncated constant.".to_string(), start))?;
+    match chunk.constants().get(index) {
+        Some(Value::String(name)) => Ok(name.clone()),
+        Some(_) => Err((format!("Constant {} isn't a name.", index), start)),
+        None => Err((format!("Unknown constant {}.", index), start)),
+    }
+}
+
+fn undefined(name: &ObjString, start: usize) -> (String, usize) {
+    (format!("Undefined variable '{}'.", name.as_str()), start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_walk::interpret::Capture;
+
+    fn chunk(values: &[f32], opcodes: &[OpCode]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for value in values {
+            chunk.add_constant(Value::Number(*value), 1);
+        }
+        for opcode in opcodes {
+            chunk.add_instruction(*opcode, 1);
+        }
+        chunk
+    }
+
+    fn executed(chunk: &Chunk) -> InterpretResult {
+        VirtualMachine::new().execute(chunk)
+    }
+
+    #[test]
+    fn test_negate() {
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(-5.0))),
+            executed(&chunk(&[5.0], &[OpCode::Negate, OpCode::Return]))
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        for (opcode, result) in [
+            (OpCode::Add, 7.0),
+            (OpCode::Subtract, 3.0),
+            (OpCode::Multiply, 10.0),
+            (OpCode::Divide, 2.5),
+        ] {
+            assert_eq!(
+                InterpretResult::Ok(Some(Value::Number(result))),
+                executed(&chunk(&[5.0, 2.0], &[opcode, OpCode::Return]))
+            );
+        }
+    }
+
+    #[test]
+    fn test_expression() {
+        // -((1.2 + 3.4) / 5.6)
+        let mut code = Chunk::new();
+        code.add_immediate(1.2, 1);
+        code.add_immediate(3.4, 1);
+        code.add_instruction(OpCode::Add, 1);
+        code.add_immediate(5.6, 1);
+        code.add_instruction(OpCode::Divide, 1);
+        code.add_instruction(OpCode::Negate, 1);
+        code.add_instruction(OpCode::Return, 1);
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(-(1.2 + 3.4) / 5.6))),
+            executed(&code)
+        );
+    }
+
+    #[test]
+    fn test_return_nothing() {
+        assert_eq!(
+            InterpretResult::Ok(None),
+            executed(&chunk(&[], &[OpCode::Return]))
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Missing return.".to_string(),
+                line: 1
+            },
+            executed(&chunk(&[5.0], &[]))
+        );
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Stack underflow.".to_string(),
+                line: 1
+            },
+            executed(&chunk(&[5.0], &[OpCode::Add, OpCode::Return]))
+        );
+
+        let mut code = Chunk::new();
+        code.add_instruction(OpCode::Negate, 4);
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Stack underflow.".to_string(),
+                line: 4
+            },
+            executed(&code)
+        );
+    }
+
+    #[test]
+    fn test_error_lines() {
+        // an instruction per line, the last of them failing
+        for failing in 1..40 {
+            let mut code = Chunk::new();
+            code.add_constant(Value::Number(1.0), 0);
+            for line in 1..failing {
+                code.add_instruction(OpCode::Negate, line);
+            }
+            code.add_instruction(OpCode::Add, failing);
+            code.add_instruction(OpCode::Return, failing + 1);
+            assert_eq!(
+                InterpretResult::RuntimeError {
+                    message: "Stack underflow.".to_string(),
+                    line: failing
+                },
+                executed(&code)
+            );
+        }
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut vm = VirtualMachine::new();
+        let InterpretResult::Ok(Some(Value::String(s))) =
+            vm.interpret("\"con\" + \"cat\" + \"enate\"")
+        else {
+            panic!("not a string");
+        };
+        assert_eq!("concatenate", s.as_str());
+        // the result is interned, so it's the same object as the literal
+        assert!(std::rc::Rc::ptr_eq(&s, &vm.strings().intern("concatenate")));
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::String(s))),
+            vm.interpret("\"concatenate\"")
+        );
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let mut vm = VirtualMachine::new();
+        for (source, message) in [
+            ("-\"a\"", "Operand must be a number."),
+            ("1 + \"a\"", "Operands must be two numbers or two strings."),
+            ("\"a\" + 1", "Operands must be two numbers or two strings."),
+            ("\"a\" - \"b\"", "Operands must be numbers."),
+            ("2 *\n\"b\"", "Operands must be numbers."),
+            ("\"a\" / 2", "Operands must be numbers."),
+        ] {
+            assert_eq!(
+                InterpretResult::RuntimeError {
+                    message: message.to_string(),
+                    line: 0
+                },
+                vm.interpret(source),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_interpret() {
+        let mut vm = VirtualMachine::new();
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(-5.0))),
+            vm.interpret("1 + 2 * -3")
+        );
+        assert_eq!(
+            InterpretResult::CompileError {
+                message: "Expect expression.".to_string(),
+                line: 1
+            },
+            vm.interpret("1 +\n")
+        );
+        assert_eq!(
+            InterpretResult::CompileError {
+                message: "Unexpected character '#'.".to_string(),
+                line: 0
+            },
+            vm.interpret("#")
+        );
+        assert_eq!(
+            InterpretResult::CompileError {
+                message: "Can't compile '!' yet.".to_string(),
+                line: 0
+            },
+            vm.interpret("!1")
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let output = Capture::default();
+        let mut vm = VirtualMachine::with_trace(output.clone());
+        vm.interpret("1 +\n2");
+        assert_eq!(
+            "          \n\
+             0    0 0x08 00 Constant 0 '1.0'\n          [ 1 ]\n\
+             2    1 0x08 01 Constant 1 '2.0'\n          [ 1 ][ 2 ]\n\
+             4    0 0x04 Add\n          [ 3 ]\n\
+             5    | 0x01 Return\n",
+            output.contents()
+        );
+    }
+
+    #[test]
+    fn test_long_constant() {
+        let mut code = Chunk::new();
+        for n in 0..=256 {
+            code.add_constant(Value::Number(n as f32), 1);
+        }
+        code.add_instruction(OpCode::Return, 1);
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(256.0))),
+            executed(&code)
+        );
+    }
+
+    #[test]
+    fn test_bad_constants() {
+        let mut code = Chunk::new();
+        code.add_instruction(OpCode::Constant, 3);
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Truncated constant.".to_string(),
+                line: 3
+            },
+            executed(&code)
+        );
+
+        // Return's byte read as an index into an empty table
+        let mut code = Chunk::new();
+        code.add_instruction(OpCode::Constant, 4);
+        code.add_instruction(OpCode::Return, 4);
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Unknown constant 1.".to_string(),
+                line: 4
+            },
+            executed(&code)
+        );
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut vm = VirtualMachine::new();
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(6.0))),
+            vm.interpret("var a = 1; var b; a = b = a + 2; a + b")
+        );
+        assert_eq!(InterpretResult::Ok(None), vm.interpret("var c;"));
+        // globals outlive the chunk that defined them, as at the prompt
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(3.0))),
+            vm.interpret("a")
+        );
+        assert_eq!(InterpretResult::Ok(Some(Value::Nil)), vm.interpret("c"));
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(4.0))),
+            vm.interpret("var a = a + 1; a")
+        );
+    }
+
+    #[test]
+    fn test_locals() {
+        let mut vm = VirtualMachine::new();
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(13.0))),
+            vm.interpret(
+                "var r; { var a = 1; { var b = a + 1; var a = b * 5; r = a + b; } r = r + a; } r"
+            )
+        );
+        // a block leaves nothing on the stack behind it
+        assert_eq!(
+            InterpretResult::Ok(None),
+            vm.interpret("{ var a; var b = 1; { var c = b; } }")
+        );
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(2.0))),
+            vm.interpret("{ var a = 1; a = a + 1; r = a; } r")
+        );
+        assert_eq!(
+            InterpretResult::Ok(Some(Value::Number(2.0))),
+            vm.interpret("{ var a = 1; var b = a = 2; r = b; } r")
+        );
+    }
+
+    #[test]
+    fn test_undefined_variables() {
+        let mut vm = VirtualMachine::new();
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Undefined variable 'b'.".to_string(),
+                line: 2
+            },
+            vm.interpret("var a = 1;\n\na + b")
+        );
+        // assigning an undefined global fails and doesn't define it
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Undefined variable 'b'.".to_string(),
+                line: 1
+            },
+            vm.interpret("a =\nb = 2;")
+        );
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Undefined variable 'b'.".to_string(),
+                line: 0
+            },
+            vm.interpret("b")
+        );
+        // a local shadowing a missing global is fine
+        assert_eq!(
+            InterpretResult::Ok(None),
+            vm.interpret("{ var b = 1; b = b + 1; }")
+        );
+    }
+
+    #[test]
+    fn test_bad_variable_instructions() {
+        let mut code = Chunk::new();
+        code.add_constant(Value::Number(1.0), 5);
+        code.add_byte_instruction(OpCode::GetGlobal, 0, 6);
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Constant 0 isn't a name.".to_string(),
+                line: 6
+            },
+            executed(&code)
+        );
+
+        let mut code = Chunk::new();
+        code.add_instruction(OpCode::Nil, 1);
+        code.add_byte_instruction(OpCode::GetLocal, 1, 2);
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Unknown local 1.".to_string(),
+                line: 2
+            },
+            executed(&code)
+        );
+
+        let mut code = Chunk::new();
+        code.add_instruction(OpCode::Pop, 3);
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Stack underflow.".to_string(),
+                line: 3
+            },
+            executed(&code)
+        );
+    }
+
+    #[test]
+    fn test_truncated_immediate() {
+        let mut code = Chunk::new();
+        code.add_instruction(OpCode::Immediate, 2);
+        assert_eq!(
+            InterpretResult::RuntimeError {
+                message: "Truncated immediate.".to_string(),
+                line: 2
+            },
+            executed(&code)
+        );
+    }
+}
@@ -0,0 +1,35 @@
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_virtual_machine"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_compile_only_needs_source() {
+    let output = std::env::temp_dir().join(format!("compile-only-{}.loxc", std::process::id()));
+    let output = output.to_str().unwrap();
+
+    // with nothing to compile it's an error, rather than a prompt
+    assert!(!run(&["--compile-only", "-o", output]).status.success());
+
+    assert!(run(&["--compile-only", "-o", output, "-e", "1 + 2"])
+        .status
+        .success());
+    let ran = run(&[output]);
+    assert!(ran.status.success());
+    assert_eq!("3\n", String::from_utf8_lossy(&ran.stdout));
+    std::fs::remove_file(output).unwrap();
+}
+
+#[test]
+fn test_unreadable_script() {
+    let ran = run(&["/nonexistent/script.lox"]);
+    assert_eq!(Some(66), ran.status.code());
+    assert!(
+        String::from_utf8_lossy(&ran.stderr).starts_with("Can't read /nonexistent/script.lox: ")
+    );
+}